@@ -0,0 +1,170 @@
+//! Files shared in messages, and the ones still uploading in the composer.
+
+use yew::prelude::*;
+
+use crate::components::links;
+use crate::protocol::Attachment;
+use crate::services::uploads::{Upload, UploadStatus};
+
+/// Images as thumbnails, other files as cards to download them from.
+pub fn view_attachments(attachments: &[Attachment]) -> Html {
+    if attachments.is_empty() {
+        return html! {};
+    }
+    let (images, files): (Vec<&Attachment>, Vec<&Attachment>) =
+        attachments.iter().partition(|attachment| is_image(attachment));
+
+    html! {
+        <div class="mt-3 space-y-2">
+            {
+                if images.is_empty() {
+                    html! {}
+                } else {
+                    html! {
+                        <div class="flex flex-wrap gap-2">{images.into_iter().map(view_image).collect::<Html>()}</div>
+                    }
+                }
+            }
+            {files.into_iter().map(view_file).collect::<Html>()}
+        </div>
+    }
+}
+
+fn is_image(attachment: &Attachment) -> bool {
+    attachment.content_type.starts_with("image/") && links::is_web_url(&attachment.url)
+}
+
+fn view_image(attachment: &Attachment) -> Html {
+    let src = attachment
+        .thumbnail
+        .clone()
+        .filter(|thumbnail| links::is_web_url(thumbnail))
+        .unwrap_or_else(|| attachment.url.clone());
+    html! {
+        <a href={attachment.url.clone()} target="_blank" rel="noopener noreferrer" title={attachment.name.clone()}>
+            <img class="max-w-xs max-h-60 rounded-xl border border-cyan-500/30 shadow-lg shadow-cyan-400/20"
+                {src}
+                alt={attachment.name.clone()}
+                loading="lazy"
+                referrerpolicy="no-referrer"/>
+        </a>
+    }
+}
+
+fn view_file(attachment: &Attachment) -> Html {
+    let download = if links::is_web_url(&attachment.url) {
+        html! {
+            <a href={attachment.url.clone()} download={attachment.name.clone()} target="_blank" rel="noopener noreferrer"
+                class="px-3 py-1 text-xs font-mono text-cyan-300 border border-cyan-400/30 rounded-lg hover:bg-cyan-400/10 transition-colors">
+                {"DOWNLOAD"}
+            </a>
+        }
+    } else {
+        html! {}
+    };
+
+    html! {
+        <div class="flex items-center gap-3 max-w-md px-3 py-2 bg-slate-900/50 border border-slate-600/50 rounded-lg">
+            <span class="text-2xl">{"📄"}</span>
+            <div class="flex-1 min-w-0">
+                <div class="text-sm text-gray-100 truncate" title={attachment.name.clone()}>{&attachment.name}</div>
+                <div class="text-xs text-slate-400 font-mono">{format_size(attachment.size)}</div>
+            </div>
+            {download}
+        </div>
+    }
+}
+
+/// The uploads waiting to go with the next message, each with its
+/// progress and a button that calls `on_remove` with its id, cancelling
+/// it if it is still going.
+pub fn view_uploads<'a>(uploads: impl Iterator<Item = &'a Upload>, on_remove: &Callback<String>) -> Html {
+    let chips: Vec<Html> = uploads.map(|upload| view_upload(upload, on_remove)).collect();
+    if chips.is_empty() {
+        return html! {};
+    }
+
+    html! {
+        <div class="flex flex-wrap gap-2 max-w-6xl mx-auto mb-3">{chips}</div>
+    }
+}
+
+fn view_upload(upload: &Upload, on_remove: &Callback<String>) -> Html {
+    let (state, percent) = match &upload.status {
+        UploadStatus::Uploading => {
+            let percent = (upload.received * 100).checked_div(upload.size).unwrap_or(0);
+            (
+                html! { <span class="text-cyan-300">{format!("{}%", percent)}</span> },
+                percent,
+            )
+        }
+        UploadStatus::Done(_) => (html! { <span class="text-green-400">{"READY"}</span> }, 100),
+        UploadStatus::Failed(reason) => (
+            html! { <span class="text-red-400" title={reason.clone()}>{"FAILED"}</span> },
+            0,
+        ),
+    };
+    let (label, title) = if upload.status == UploadStatus::Uploading {
+        ("CANCEL", "Cancel upload")
+    } else {
+        ("×", "Remove")
+    };
+    let onclick = {
+        let id = upload.id.clone();
+        on_remove.reform(move |_| id.clone())
+    };
+
+    html! {
+        <div class="relative w-56 px-3 py-2 bg-slate-800/80 border border-cyan-500/30 rounded-xl overflow-hidden">
+            <div class="flex items-center justify-between gap-2 text-xs font-mono">
+                <span class="truncate text-gray-100" title={upload.name.clone()}>{&upload.name}</span>
+                <button {onclick} {title} class="flex-none text-slate-400 hover:text-red-300 transition-colors">{label}</button>
+            </div>
+            <div class="flex items-center justify-between mt-1 text-xs font-mono">
+                <span class="text-slate-400">{format_size(upload.size)}</span>
+                {state}
+            </div>
+            <div class="absolute bottom-0 left-0 h-0.5 bg-cyan-400 transition-all" style={format!("width: {}%", percent)}></div>
+        </div>
+    }
+}
+
+/// "2.4 MB"
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    // Up a unit as soon as one decimal would round to 1024.0.
+    while size >= 1023.95 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_below_a_kilobyte_are_in_bytes() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+    }
+
+    #[test]
+    fn sizes_around_unit_boundaries() {
+        assert_eq!(format_size(1024), "1.0 KB");
+        assert_eq!(format_size(1536), "1.5 KB");
+        assert_eq!(format_size(1024 * 1024 - 1), "1.0 MB");
+        assert_eq!(format_size(1024 * 1024 - 1024), "1023.0 KB");
+        assert_eq!(format_size(1024 * 1024), "1.0 MB");
+        assert_eq!(format_size(25 * 1024 * 1024), "25.0 MB");
+        assert_eq!(format_size(1024 * 1024 * 1024), "1.0 GB");
+        assert_eq!(format_size(5 * 1024 * 1024 * 1024 * 1024), "5120.0 GB");
+    }
+}
@@ -1,63 +1,445 @@
-use serde::{Deserialize, Serialize};
-use web_sys::{HtmlInputElement, KeyboardEvent};
+use std::collections::{BTreeMap, HashSet};
+
+use gloo::file::File;
+use gloo::timers::callback::{Interval, Timeout};
+use gloo::utils::document;
+use wasm_bindgen::JsCast;
+use web_sys::{DataTransfer, DragEvent, FileList, HtmlInputElement, HtmlSelectElement, KeyboardEvent};
 use yew::prelude::*;
 use yew_agent::{Bridge, Bridged};
+use yew_router::prelude::*;
 
-use crate::services::event_bus::EventBus;
-use crate::{services::websocket::WebsocketService, User};
+use crate::components::attachments;
+use crate::components::code_block::DEFAULT_COLLAPSE_AFTER;
+use crate::components::links;
+use crate::components::markdown;
+use crate::components::previews;
+use crate::components::reactions;
+use crate::components::roster::{status_label, Roster, UserProfile};
+use crate::components::time;
+use crate::components::typing::{TypingNotifier, TypingTracker, STOP_AFTER_MS};
+use crate::protocol::{
+    Attachment, ChatMessage, ClientFrame, Conversation, ErrorCode, LinkPreview, Presence, ServerFrame, UploadChunk,
+    DEFAULT_ROOM,
+};
+use crate::services::activity::ActivityMonitor;
+use crate::services::auth::Token;
+use crate::services::event_bus::{Event, EventBus, Request, Topic, UiEvent};
+use crate::services::outbox::{DeliveryStatus, Outbox, OutgoingMessage};
+use crate::services::session::Session;
+use crate::services::storage::MessageCache;
+use crate::services::uploads::Uploads;
+use crate::services::websocket::{ConnectionState, Outgoing, Registered, WebsocketService};
+use crate::{Route, User};
 
 pub enum Msg {
-    HandleMsg(String),
+    Received(ServerFrame),
+    ConnectionChanged(ConnectionState),
+    Notice(String),
     SubmitMessage,
+    Retry(String),
     OnKeyPress(KeyboardEvent),
+    /// Keys `keypress` does not see: Up to edit, Escape to cancel an edit
+    /// or a reply.
+    OnKeyDown(KeyboardEvent),
     UpdateInput(String),
+    OnRoomKeyPress(KeyboardEvent),
+    Close,
+    MessagesScrolled,
+    /// No keystroke for a while, tell others we stopped typing.
+    TypingIdle,
+    /// Someone's `typing` may have run out.
+    TypingExpired,
+    SetPresence(Presence),
+    /// Code blocks with more lines than this start collapsed.
+    SetCollapseCodeAfter(usize),
+    Idle(bool),
+    /// Check which messages scrolled into view.
+    MarkRead,
+    /// Time passed, redraw relative times.
+    Tick,
+    /// The "new messages" button was clicked.
+    JumpToLatest,
+    /// Load one of our messages into the composer to edit it.
+    StartEdit(String),
+    CancelEdit,
+    DeleteMessage(String),
+    /// Show or hide the earlier versions of an edited message.
+    ToggleEdits(String),
+    /// Open or close the emoji picker of a message.
+    ToggleReactionPicker(String),
+    /// Add our reaction to a message, or take it back if we already did.
+    React { id: String, emoji: String },
+    /// Answer a message from the composer, quoting it.
+    ReplyTo(String),
+    CancelReply,
+    OpenThread(String),
+    CloseThread,
+    UpdateThreadInput(String),
+    OnThreadKeyPress(KeyboardEvent),
+    SubmitThreadReply,
+    /// Take the preview of a link off one of our messages.
+    DismissPreview { id: String, url: String },
+    /// Files picked, dropped or pasted into the composer.
+    AttachFiles(Vec<File>),
+    /// Cancel an upload, or clear a finished or failed one.
+    RemoveUpload(String),
+    /// The next chunk of an upload was read from disk.
+    ChunkRead {
+        upload_id: String,
+        chunk: Result<UploadChunk, String>,
+    },
+    /// Files are being dragged over the conversation, or stopped being.
+    Dragging(bool),
+    TokenRefreshed(Token),
+    SessionExpired,
+    Logout,
+}
+
+#[derive(Properties, PartialEq)]
+pub struct Props {
+    pub conversation: Conversation,
+}
+
+/// Consecutive messages from one sender this close together are grouped.
+const GROUP_WINDOW_MS: f64 = 5.0 * 60_000.0;
+/// Line counts offered for collapsing code blocks.
+const COLLAPSE_CHOICES: [usize; 5] = [5, 10, DEFAULT_COLLAPSE_AFTER, 30, 50];
+/// How often relative times like "2 minutes ago" are redrawn.
+const CLOCK_REFRESH_MS: u32 = 30_000;
+
+/// How close to the bottom of the messages pane, in pixels, still counts
+/// as reading the latest messages.
+const STICK_TO_BOTTOM_THRESHOLD: i32 = 32;
+
+/// Quotes of the message a reply answers are cut after this many
+/// characters.
+const QUOTE_LENGTH: usize = 80;
+
+/// Messages requested per history page.
+const HISTORY_PAGE_SIZE: u32 = 50;
+/// How close to the top of the messages pane, in pixels, older history is
+/// fetched.
+const HISTORY_SCROLL_THRESHOLD: i32 = 48;
+
+/// What to do with the scroll position of the messages pane once the next
+/// render is on screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scroll {
+    Keep,
+    Bottom,
+    /// Older history went in on top. Keep the same messages in view by
+    /// holding the distance from the bottom, taken before the render.
+    Anchor { from_bottom: i32 },
 }
 
-#[derive(Clone, Debug, Deserialize)]
-struct MessageData {
-    from: String,
-    message: String,
-    timestamp: Option<String>,
+/// How a single message is drawn.
+struct Bubble<'a> {
+    from: &'a str,
+    body: &'a str,
+    sent_at: Option<f64>,
+    /// Same sender as the message above, shortly before it.
+    continued: bool,
+    deleted: bool,
+    status: Html,
+    /// Actions shown on hover.
+    menu: Html,
+    /// The "(edited)" marker.
+    edits: Html,
+    reactions: Html,
+    /// The message this one answers.
+    quote: Html,
+    /// How many replies there are, opening the thread.
+    replies: Html,
+    /// Cards for the links in the message.
+    previews: Html,
+    attachments: Html,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "lowercase")]
-pub enum MsgTypes {
-    Users,
-    Register,
-    Message,
-    Error,
+struct Timeline {
+    messages: Vec<ChatMessage>,
+    unread: usize,
+    loading_history: bool,
+    has_more_history: bool,
+    /// The latest history page arrived on the current connection. Until
+    /// then the timeline only has what was cached or came in live.
+    latest_loaded: bool,
+    /// Newest message we have seen, as last told to the server.
+    read_cursor: Option<String>,
+    /// Draw the "new messages" divider after this message.
+    divider_after: Option<String>,
+    /// Newest message each other member has seen, by username.
+    read_by: BTreeMap<String, String>,
+    /// Replies to each message whose thread was opened. Replies that are
+    /// also in `messages` are here too.
+    threads: BTreeMap<String, Vec<ChatMessage>>,
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct WebSocketMessage {
-    message_type: MsgTypes,
-    data_array: Option<Vec<String>>,
-    data: Option<String>,
+impl Default for Timeline {
+    fn default() -> Self {
+        Self {
+            messages: vec![],
+            unread: 0,
+            loading_history: false,
+            has_more_history: true,
+            latest_loaded: false,
+            read_cursor: None,
+            divider_after: None,
+            read_by: BTreeMap::new(),
+            threads: BTreeMap::new(),
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
-struct UserProfile {
-    name: String,
-    avatar: String,
-    is_online: bool,
+impl Timeline {
+    fn cached(messages: Vec<ChatMessage>) -> Self {
+        Self {
+            messages,
+            ..Self::default()
+        }
+    }
+
+    fn contains(&self, id: &str) -> bool {
+        self.messages.iter().any(|m| m.id.as_deref() == Some(id))
+    }
+
+    /// Appends a live message, ignoring ones we already have. Replies also
+    /// go into their thread, and only there when posted in the thread.
+    fn push(&mut self, message: ChatMessage) -> bool {
+        self.insert(self.messages.len(), message, true)
+    }
+
+    /// Like [`Timeline::push`], but puts the message at `index`. Without
+    /// `count_reply`, a reply is not counted on the message it answers,
+    /// whose count already includes it.
+    fn insert(&mut self, index: usize, message: ChatMessage, count_reply: bool) -> bool {
+        if let Some(id) = &message.id {
+            if self.contains(id) {
+                return false;
+            }
+        }
+        if let Some(parent) = &message.reply_to {
+            if !self.add_reply(parent, &message, count_reply) {
+                return false;
+            }
+        }
+        if !message.in_thread {
+            self.messages.insert(index, message);
+        }
+        true
+    }
+
+    /// Files a reply under its thread, if that is loaded, and counts it on
+    /// the message it answers if `count`. Returns `false` for a reply we
+    /// already have.
+    fn add_reply(&mut self, parent: &str, reply: &ChatMessage, count: bool) -> bool {
+        if let Some(replies) = self.threads.get_mut(parent) {
+            if reply.id.is_some() && replies.iter().any(|r| r.id == reply.id) {
+                return false;
+            }
+            replies.push(reply.clone());
+        }
+        if count {
+            for message in self.copies_mut(parent) {
+                message.reply_count += 1;
+            }
+        }
+        true
+    }
+
+    /// Takes the replies from a `thread` frame.
+    fn set_thread(&mut self, parent: &str, replies: Vec<ChatMessage>) {
+        let count = replies.len() as u32;
+        for message in self.copies_mut(parent) {
+            message.reply_count = count;
+        }
+        self.threads.insert(parent.to_owned(), replies);
+    }
+
+    /// Message `id`, in the conversation or in a thread.
+    fn find(&self, id: &str) -> Option<&ChatMessage> {
+        self.messages
+            .iter()
+            .chain(self.threads.values().flatten())
+            .find(|m| m.id.as_deref() == Some(id))
+    }
+
+    /// Every copy of message `id`, as a reply can be both in the
+    /// conversation and in its thread.
+    fn copies_mut<'a>(&'a mut self, id: &'a str) -> impl Iterator<Item = &'a mut ChatMessage> + 'a {
+        self.messages
+            .iter_mut()
+            .chain(self.threads.values_mut().flatten())
+            .filter(move |m| m.id.as_deref() == Some(id))
+    }
+
+    /// Merges a history page. Messages of the page that come before the
+    /// first one we already know are older and go in front, the others go
+    /// right after the last known one before them, which puts those after
+    /// the last known one at the end. The reply counts of the page already
+    /// include the replies in it.
+    fn merge_history(&mut self, page: Vec<ChatMessage>, older: bool) {
+        if !older {
+            self.latest_loaded = true;
+        }
+        let in_page: HashSet<String> = page.iter().filter_map(|m| m.id.clone()).collect();
+        let known = |m: &ChatMessage| m.id.as_deref().is_some_and(|id| self.contains(id));
+        let mut at = if older || self.messages.is_empty() || page.iter().any(known) {
+            0
+        } else {
+            self.messages.len()
+        };
+
+        for message in page {
+            match message.id.as_deref().and_then(|id| self.position(id)) {
+                Some(position) => {
+                    // What we already have may have been edited or deleted since.
+                    self.replace(message);
+                    at = position + 1;
+                }
+                None => {
+                    let shown = !message.in_thread;
+                    let counted = message.reply_to.as_ref().is_some_and(|parent| in_page.contains(parent));
+                    if self.insert(at, message, !counted) && shown {
+                        at += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Swaps in a newer version of a message we have, e.g. after an edit.
+    fn replace(&mut self, message: ChatMessage) -> bool {
+        let Some(id) = message.id.clone() else {
+            return false;
+        };
+        let mut found = false;
+        for old in self.copies_mut(&id) {
+            let timestamp = message.timestamp.clone().or_else(|| old.timestamp.take());
+            *old = ChatMessage {
+                timestamp,
+                ..message.clone()
+            };
+            found = true;
+        }
+        found
+    }
+
+    fn tombstone(&mut self, id: &str) -> bool {
+        let mut changed = false;
+        for message in self.copies_mut(id).filter(|m| !m.deleted) {
+            message.tombstone();
+            changed = true;
+        }
+        changed
+    }
+
+    fn react(&mut self, id: &str, user: &str, emoji: &str, add: bool) -> bool {
+        let mut changed = false;
+        for message in self.copies_mut(id).filter(|m| !m.deleted) {
+            changed |= message.react(user, emoji, add);
+        }
+        changed
+    }
+
+    fn set_previews(&mut self, id: &str, previews: &[LinkPreview]) -> bool {
+        let mut changed = false;
+        for message in self.copies_mut(id).filter(|m| !m.deleted && m.previews != previews) {
+            message.previews = previews.to_vec();
+            changed = true;
+        }
+        changed
+    }
+
+    /// Our newest message that can still be edited, for the Up key.
+    fn last_editable(&self, me: &str) -> Option<&ChatMessage> {
+        self.messages
+            .iter()
+            .rev()
+            .find(|m| m.from == me && m.id.is_some() && !m.deleted)
+    }
+
+    fn oldest_id(&self) -> Option<String> {
+        self.messages.first().and_then(|m| m.id.clone())
+    }
+
+    fn position(&self, id: &str) -> Option<usize> {
+        self.messages.iter().position(|m| m.id.as_deref() == Some(id))
+    }
+
+    /// Moves our read cursor to `id` unless it already is further down.
+    /// A cursor we cannot find is older than anything loaded.
+    fn advance_cursor(&mut self, id: &str, me: &str) -> bool {
+        let Some(new) = self.position(id) else {
+            return false;
+        };
+        if self.read_cursor.as_deref().and_then(|cursor| self.position(cursor)) >= Some(new) {
+            return false;
+        }
+        self.read_cursor = Some(id.to_owned());
+        self.unread = self.messages[new + 1..].iter().filter(|m| m.from != me).count();
+        true
+    }
+
+    /// Where the divider goes when the user comes back: after what they
+    /// read last, if anything newer arrived since.
+    fn place_divider(&mut self) {
+        self.divider_after = self
+            .read_cursor
+            .clone()
+            .filter(|cursor| self.messages.last().and_then(|m| m.id.as_ref()) != Some(cursor));
+    }
 }
 
 pub struct Chat {
-    users: Vec<UserProfile>,
+    users: Roster,
+    /// Status picked by the user, see [`Chat::presence`].
+    presence: Presence,
+    idle: bool,
+    _activity: ActivityMonitor,
+    typing: TypingTracker,
+    typing_notifier: TypingNotifier,
+    typing_idle: Option<Timeout>,
+    typing_expiry: Option<Timeout>,
+    _clock: Interval,
+    scroll: Scroll,
+    /// Messages that arrived below the fold while scrolled up.
+    new_below: usize,
+    /// Id of our message the composer is editing, if any.
+    editing: Option<String>,
+    /// Id of the message whose earlier versions are shown.
+    showing_edits: Option<String>,
+    /// Id of the message whose emoji picker is open.
+    reaction_picker: Option<String>,
+    /// Id of the message the composer answers, if any.
+    replying_to: Option<String>,
+    /// Id of the message whose thread is open in the side panel.
+    thread: Option<String>,
+    thread_input: String,
+    thread_input_ref: NodeRef,
+    uploads: Uploads,
+    /// Files are being dragged over the conversation.
+    dragging: bool,
     chat_input: NodeRef,
+    messages_ref: NodeRef,
     input_value: String,
     _producer: Box<dyn Bridge<EventBus>>,
     wss: WebsocketService,
-    messages: Vec<MessageData>,
-    is_connected: bool,
+    room_input: NodeRef,
+    conversations: BTreeMap<Conversation, Timeline>,
+    available_rooms: Vec<String>,
+    current: Conversation,
+    username: String,
+    user: User,
+    outbox: Outbox,
+    cache: MessageCache,
+    connection: ConnectionState,
     error_message: Option<String>,
 }
 
 impl Component for Chat {
     type Message = Msg;
-    type Properties = ();
+    type Properties = Props;
 
     fn create(ctx: &Context<Self>) -> Self {
         // Get user from context
@@ -66,100 +448,375 @@ impl Component for Chat {
             .context::<User>(Callback::noop())
             .expect("context to be set");
         
-        // Create WebSocket service
-        let wss = WebsocketService::new();
-        let username = user.username.borrow().clone();
-
-        // Create registration message
-        let message = WebSocketMessage {
-            message_type: MsgTypes::Register,
-            data: Some(username.to_string()),
-            data_array: None,
+        let session = match &*user.session.borrow() {
+            Some(session) => session.clone(),
+            None => unreachable!("chat is only routed to with a session"),
         };
+        let username = session.username.clone();
 
-        // Try to send registration message
-        let is_connected = match serde_json::to_string(&message) {
-            Ok(json) => wss.tx.clone().try_send(json).is_ok(),
-            Err(e) => {
-                log::error!("Failed to serialize message: {:?}", e);
-                false
+        // Take over the connection Login registered on. After a reload there
+        // is none, so register again, as on every reconnect.
+        let registered = user.connection.borrow_mut().take();
+        let wss = match registered {
+            Some(Registered { service, users }) => {
+                ctx.link().send_message(Msg::Received(ServerFrame::Users { users }));
+                ctx.link().send_message(Msg::ConnectionChanged(ConnectionState::Connected));
+                service
             }
+            None => WebsocketService::new(username.clone(), session.server, session.token),
         };
 
-        log::debug!("User registration status: {}", is_connected);
+        let mut producer = EventBus::bridge(ctx.link().callback(|event| match event {
+            Event::Server(frame) => Msg::Received(frame),
+            Event::Connection(state) => Msg::ConnectionChanged(state),
+            Event::Ui(UiEvent::Notice(notice)) => Msg::Notice(notice),
+            Event::Ui(UiEvent::TokenRefreshed(token)) => Msg::TokenRefreshed(token),
+            Event::Ui(UiEvent::SessionExpired) => Msg::SessionExpired,
+        }));
+        producer.send(Request::Subscribe(vec![Topic::Server, Topic::Connection, Topic::Ui]));
 
-        Self {
-            users: vec![],
-            messages: vec![],
+        // Show whatever we cached last time right away, the server catches
+        // up once connected
+        let cache = MessageCache::new(&username);
+        let current = ctx.props().conversation.clone();
+        let mut conversations = BTreeMap::new();
+        for conversation in [Conversation::default_room(), current.clone()] {
+            let timeline = Timeline::cached(cache.load(&conversation));
+            conversations.insert(conversation, timeline);
+        }
+
+        let chat = Self {
+            users: Roster::default(),
+            presence: session.preferences.presence.unwrap_or(Presence::Online),
+            idle: false,
+            _activity: ActivityMonitor::new(ctx.link().callback(Msg::Idle)),
+            typing: TypingTracker::default(),
+            typing_notifier: TypingNotifier::default(),
+            typing_idle: None,
+            typing_expiry: None,
+            scroll: Scroll::Bottom,
+            new_below: 0,
+            editing: None,
+            showing_edits: None,
+            reaction_picker: None,
+            replying_to: None,
+            thread: None,
+            thread_input: String::new(),
+            thread_input_ref: NodeRef::default(),
+            uploads: Uploads::default(),
+            dragging: false,
+            _clock: {
+                let link = ctx.link().clone();
+                Interval::new(CLOCK_REFRESH_MS, move || link.send_message(Msg::Tick))
+            },
+            room_input: NodeRef::default(),
+            conversations,
+            available_rooms: vec![],
+            current,
             chat_input: NodeRef::default(),
+            messages_ref: NodeRef::default(),
             input_value: String::new(),
             wss,
-            is_connected,
+            outbox: Outbox::load(&username),
+            cache,
+            username,
+            user,
+            connection: ConnectionState::Connecting,
             error_message: None,
-            _producer: EventBus::bridge(ctx.link().callback(Msg::HandleMsg)),
+            _producer: producer,
+        };
+        chat.remember_conversation();
+        chat
+    }
+
+    fn changed(&mut self, ctx: &Context<Self>) -> bool {
+        let conversation = ctx.props().conversation.clone();
+        if conversation == self.current {
+            return false;
+        }
+
+        let known = self.conversations.contains_key(&conversation);
+        if !known && self.connection == ConnectionState::Connected {
+            if let Conversation::Room(room) = &conversation {
+                self.send_frame(ClientFrame::Join { room: room.clone() });
+            }
         }
+        self.timeline(&conversation).place_divider();
+        self.request_latest(&conversation);
+        self.stop_typing();
+        self.cancel_edit();
+        self.replying_to = None;
+        self.thread = None;
+        self.scroll = Scroll::Bottom;
+        self.new_below = 0;
+        self.current = conversation;
+        self.remember_conversation();
+        true
     }
 
-    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
-            Msg::HandleMsg(s) => {
-                match serde_json::from_str::<WebSocketMessage>(&s) {
-                    Ok(msg) => {
-                        self.error_message = None;
-                        match msg.message_type {
-                            MsgTypes::Users => {
-                                let users_from_message = msg.data_array.unwrap_or_default();
-                                self.users = users_from_message
-                                    .iter()
-                                    .map(|u| UserProfile {
-                                        name: u.clone(),
-                                        avatar: format!(
-                                            "https://avatars.dicebear.com/api/adventurer-neutral/{}.svg",
-                                            u
-                                        ),
-                                        is_online: true,
-                                    })
-                                    .collect();
-                                self.is_connected = true;
-                                return true;
+            Msg::ConnectionChanged(state) => {
+                log::debug!("connection state: {:?}", state);
+                self.connection = state;
+                if state == ConnectionState::Connected {
+                    let joined: Vec<String> = self.joined_rooms().cloned().collect();
+                    for room in joined {
+                        self.send_frame(ClientFrame::Join { room });
+                    }
+                    self.send_frame(ClientFrame::ListRooms);
+                    // The server assumes online on register.
+                    if self.presence() != Presence::Online {
+                        self.send_frame(ClientFrame::Presence { status: self.presence() });
+                    }
+                    self.request_latest(&self.current.clone());
+                    self.request_thread();
+                    for frame in self.uploads.resume() {
+                        self.send_frame(frame);
+                    }
+                    self.flush_outbox();
+                } else {
+                    self.outbox.fail_in_flight();
+                    // Whatever was said meanwhile comes with the next latest page.
+                    for timeline in self.conversations.values_mut() {
+                        timeline.latest_loaded = false;
+                    }
+                }
+                true
+            }
+            Msg::Notice(notice) => {
+                self.error_message = Some(notice);
+                true
+            }
+            Msg::Received(frame) => {
+                self.error_message = None;
+                match frame {
+                    ServerFrame::Users { users } => {
+                        self.users.set_connected(&users);
+                        let presence = self.presence();
+                        self.users.update(&self.username, presence, None);
+                        true
+                    }
+                    ServerFrame::Message { mut message } | ServerFrame::Direct { mut message } => {
+                        // Legacy servers do not stamp messages, arrival is close enough.
+                        message.timestamp.get_or_insert_with(time::now_iso);
+                        let conversation = message.conversation(&self.username);
+                        match &message.client_id {
+                            Some(id) => self.outbox.acknowledge(id),
+                            None if message.from == self.username => {
+                                self.outbox.acknowledge_text(&conversation, &message.message)
                             }
-                            MsgTypes::Message => {
-                                if let Some(data) = msg.data {
-                                    match serde_json::from_str::<MessageData>(&data) {
-                                        Ok(message_data) => {
-                                            self.messages.push(message_data);
-                                            return true;
-                                        }
-                                        Err(e) => {
-                                            log::error!("Failed to parse message data: {:?}", e);
-                                            self.error_message = Some(format!("Failed to parse message data: {}", e));
-                                            return true;
-                                        }
-                                    }
-                                }
-                                return false;
+                            None => false,
+                        };
+                        self.typing.stopped(&conversation, &message.from);
+                        let from_me = message.from == self.username;
+                        // Thread replies are read in the thread, not the conversation.
+                        let in_thread = message.in_thread;
+                        let timeline = self.timeline(&conversation);
+                        let added = timeline.push(*message);
+                        if added && !from_me && !in_thread {
+                            timeline.unread += 1;
+                        }
+                        if added {
+                            self.persist(&conversation);
+                            if conversation == self.current && !in_thread {
+                                self.follow_new_message(from_me);
                             }
-                            MsgTypes::Error => {
-                                self.error_message = msg.data;
-                                self.is_connected = false;
-                                return true;
+                        }
+                        added
+                    }
+                    ServerFrame::History {
+                        conversation,
+                        before,
+                        messages,
+                        has_more,
+                        mut read_by,
+                    } => {
+                        if conversation == self.current {
+                            self.scroll = match (&before, self.messages_pane()) {
+                                (Some(_), Some(pane)) => Scroll::Anchor {
+                                    from_bottom: pane.scroll_height() - pane.scroll_top(),
+                                },
+                                (None, Some(pane)) if !is_at_bottom(&pane) => self.scroll,
+                                _ => Scroll::Bottom,
+                            };
+                        }
+                        let me = self.username.clone();
+                        let timeline = self.timeline(&conversation);
+                        timeline.loading_history = false;
+                        if before.is_some() || timeline.messages.is_empty() {
+                            timeline.has_more_history = has_more;
+                        }
+                        timeline.merge_history(messages, before.is_some());
+                        // Only the latest page has cursors worth knowing.
+                        if before.is_none() {
+                            if let Some(mine) = read_by.remove(&me) {
+                                timeline.advance_cursor(&mine, &me);
+                            }
+                            timeline.read_by = read_by;
+                        }
+                        self.persist(&conversation);
+                        true
+                    }
+                    ServerFrame::Rooms { rooms } => {
+                        self.available_rooms = rooms;
+                        true
+                    }
+                    ServerFrame::Presence {
+                        user,
+                        status,
+                        last_seen,
+                    } => {
+                        self.users.update(&user, status, last_seen.as_deref());
+                        true
+                    }
+                    ServerFrame::Typing {
+                        user,
+                        conversation,
+                        typing,
+                    } => {
+                        if user == self.username {
+                            return false;
+                        }
+                        self.typing.update(conversation, user, typing);
+                        self.schedule_typing_expiry(ctx);
+                        true
+                    }
+                    ServerFrame::Read {
+                        user,
+                        conversation,
+                        message_id,
+                    } => {
+                        let me = self.username.clone();
+                        let timeline = self.timeline(&conversation);
+                        if user == me {
+                            timeline.advance_cursor(&message_id, &me)
+                        } else {
+                            timeline.read_by.insert(user, message_id);
+                            true
+                        }
+                    }
+                    ServerFrame::Edit { message } => {
+                        let conversation = message.conversation(&self.username);
+                        let replaced = self
+                            .conversations
+                            .get_mut(&conversation)
+                            .is_some_and(|timeline| timeline.replace(*message));
+                        if replaced {
+                            self.persist(&conversation);
+                        }
+                        replaced
+                    }
+                    ServerFrame::Delete {
+                        conversation,
+                        message_id,
+                    } => {
+                        let deleted = self
+                            .conversations
+                            .get_mut(&conversation)
+                            .is_some_and(|timeline| timeline.tombstone(&message_id));
+                        if deleted {
+                            if self.editing.as_ref() == Some(&message_id) {
+                                self.cancel_edit();
                             }
-                            _ => {
-                                return false;
+                            self.persist(&conversation);
+                        }
+                        deleted
+                    }
+                    ServerFrame::Reaction {
+                        user,
+                        conversation,
+                        message_id,
+                        emoji,
+                        add,
+                    } => {
+                        let changed = self
+                            .conversations
+                            .get_mut(&conversation)
+                            .is_some_and(|timeline| timeline.react(&message_id, &user, &emoji, add));
+                        if changed {
+                            self.persist(&conversation);
+                        }
+                        changed
+                    }
+                    ServerFrame::Thread {
+                        conversation,
+                        message_id,
+                        messages,
+                    } => {
+                        let Some(timeline) = self.conversations.get_mut(&conversation) else {
+                            return false;
+                        };
+                        timeline.set_thread(&message_id, messages);
+                        self.persist(&conversation);
+                        true
+                    }
+                    ServerFrame::Unfurl {
+                        conversation,
+                        message_id,
+                        previews,
+                    } => {
+                        if conversation == self.current {
+                            // The cards make the message taller, keep it in view.
+                            if self.messages_pane().as_ref().is_some_and(is_at_bottom) {
+                                self.scroll = Scroll::Bottom;
                             }
                         }
+                        let changed = self
+                            .conversations
+                            .get_mut(&conversation)
+                            .is_some_and(|timeline| timeline.set_previews(&message_id, &previews));
+                        if changed {
+                            self.persist(&conversation);
+                        }
+                        changed
                     }
-                    Err(e) => {
-                        log::error!("Failed to parse websocket message: {:?}", e);
-                        self.error_message = Some(format!("Failed to parse server message: {}", e));
-                        return true;
+                    ServerFrame::UploadProgress { upload_id, received } => {
+                        if let Some(read) = self.uploads.progress(&upload_id, received) {
+                            ctx.link().send_future(async move {
+                                Msg::ChunkRead {
+                                    upload_id,
+                                    chunk: read.await,
+                                }
+                            });
+                        }
+                        true
                     }
+                    ServerFrame::Uploaded { upload_id, attachment } => self.uploads.finish(&upload_id, attachment),
+                    ServerFrame::UploadFailed { upload_id, reason } => self.uploads.fail(&upload_id, reason),
+                    ServerFrame::Ack { client_id } => self.outbox.acknowledge(&client_id),
+                    // The connection refreshes the token and registers again,
+                    // it tells us if the session cannot be refreshed.
+                    ServerFrame::Error {
+                        code: Some(ErrorCode::Unauthorized),
+                        ..
+                    } => false,
+                    ServerFrame::Error { reason, .. } => {
+                        self.error_message = Some(reason);
+                        true
+                    }
+                    ServerFrame::Unknown => false,
                 }
             }
             Msg::UpdateInput(value) => {
                 self.input_value = value;
+                if let Some(frame) = self.typing_notifier.input(&self.current, self.input_value.is_empty()) {
+                    self.send_frame(frame);
+                }
+                let link = ctx.link().clone();
+                self.typing_idle = Some(Timeout::new(STOP_AFTER_MS, move || {
+                    link.send_message(Msg::TypingIdle)
+                }));
                 true // Mark component for re-render
             }
+            Msg::TypingIdle => {
+                self.stop_typing();
+                false
+            }
+            Msg::TypingExpired => {
+                self.schedule_typing_expiry(ctx);
+                true
+            }
             Msg::OnKeyPress(e) => {
                 if e.key() == "Enter" {
                     self.send_message();
@@ -167,10 +824,333 @@ impl Component for Chat {
                 }
                 false
             }
+            Msg::OnKeyDown(e) => match e.key().as_str() {
+                "ArrowUp" if self.input_value.is_empty() && self.editing.is_none() => {
+                    let last = self
+                        .conversations
+                        .get(&self.current)
+                        .and_then(|timeline| timeline.last_editable(&self.username))
+                        .and_then(|m| m.id.clone());
+                    match last {
+                        Some(id) => {
+                            e.prevent_default();
+                            self.start_edit(&id)
+                        }
+                        None => false,
+                    }
+                }
+                "Escape" if self.editing.is_some() => {
+                    self.cancel_edit();
+                    true
+                }
+                "Escape" if self.replying_to.is_some() => {
+                    self.replying_to = None;
+                    true
+                }
+                _ => false,
+            },
             Msg::SubmitMessage => {
                 self.send_message();
                 true
             }
+            Msg::OnRoomKeyPress(e) => {
+                if e.key() != "Enter" {
+                    return false;
+                }
+                if let Some(input) = self.room_input.cast::<HtmlInputElement>() {
+                    // Room names end up in the URL, keep them to a safe slug
+                    let room: String = input
+                        .value()
+                        .trim()
+                        .to_lowercase()
+                        .chars()
+                        .map(|c| if c.is_whitespace() { '-' } else { c })
+                        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+                        .collect();
+                    if !room.is_empty() {
+                        input.set_value("");
+                        if let Some(history) = ctx.link().history() {
+                            history.push(Route::ChatRoom { room });
+                        }
+                    }
+                }
+                false
+            }
+            Msg::Close => {
+                if self.current != Conversation::default_room() {
+                    let closed = self.current.clone();
+                    self.conversations.remove(&closed);
+                    if let Conversation::Room(room) = closed {
+                        self.send_frame(ClientFrame::Leave { room });
+                    }
+                    if let Some(history) = ctx.link().history() {
+                        history.push(Route::ChatRoom {
+                            room: DEFAULT_ROOM.to_owned(),
+                        });
+                    }
+                }
+                false
+            }
+            Msg::MessagesScrolled => {
+                let pane = self.messages_pane();
+                let near_top = pane
+                    .as_ref()
+                    .is_some_and(|pane| pane.scroll_top() <= HISTORY_SCROLL_THRESHOLD);
+                let caught_up = self.new_below > 0 && pane.as_ref().is_some_and(is_at_bottom);
+                if caught_up {
+                    self.new_below = 0;
+                }
+                let read = self.mark_read() || caught_up;
+                if !near_top || self.connection != ConnectionState::Connected {
+                    return read;
+                }
+
+                let before = match self.conversations.get(&self.current) {
+                    Some(timeline) if timeline.has_more_history && !timeline.loading_history => timeline.oldest_id(),
+                    _ => None,
+                };
+                match before {
+                    Some(before) => {
+                        self.request_history(self.current.clone(), Some(before));
+                        true
+                    }
+                    None => read,
+                }
+            }
+            Msg::SetPresence(presence) => {
+                let before = self.presence();
+                self.presence = presence;
+                if let Some(session) = self.user.session.borrow_mut().as_mut() {
+                    session.preferences.presence = Some(presence);
+                    session.save();
+                }
+                self.presence_changed(before)
+            }
+            Msg::SetCollapseCodeAfter(lines) => {
+                if let Some(session) = self.user.session.borrow_mut().as_mut() {
+                    session.preferences.collapse_code_after = Some(lines);
+                    session.save();
+                }
+                true
+            }
+            Msg::Idle(idle) => {
+                let before = self.presence();
+                self.idle = idle;
+                // Whatever arrives while away goes below the divider.
+                if idle {
+                    self.timeline(&self.current.clone()).place_divider();
+                }
+                let read = self.mark_read();
+                self.presence_changed(before) || read || idle
+            }
+            Msg::MarkRead => self.mark_read(),
+            Msg::Tick => true,
+            Msg::JumpToLatest => {
+                self.scroll = Scroll::Bottom;
+                self.new_below = 0;
+                true
+            }
+            Msg::StartEdit(id) => self.start_edit(&id),
+            Msg::CancelEdit => {
+                self.cancel_edit();
+                true
+            }
+            Msg::DeleteMessage(id) => {
+                if !gloo::dialogs::confirm("Delete this message for everyone?") {
+                    return false;
+                }
+                if self.editing.as_ref() == Some(&id) {
+                    self.cancel_edit();
+                }
+                self.send_frame(ClientFrame::Delete {
+                    conversation: self.current.clone(),
+                    message_id: id,
+                });
+                true
+            }
+            Msg::ToggleEdits(id) => {
+                self.showing_edits = match self.showing_edits.take() {
+                    Some(shown) if shown == id => None,
+                    _ => Some(id),
+                };
+                true
+            }
+            Msg::ToggleReactionPicker(id) => {
+                self.reaction_picker = match self.reaction_picker.take() {
+                    Some(open) if open == id => None,
+                    _ => Some(id),
+                };
+                true
+            }
+            Msg::ReplyTo(id) => {
+                self.cancel_edit();
+                self.replying_to = Some(id);
+                if let Some(input) = self.chat_input.cast::<HtmlInputElement>() {
+                    let _ = input.focus();
+                }
+                true
+            }
+            Msg::CancelReply => {
+                self.replying_to = None;
+                true
+            }
+            Msg::OpenThread(id) => {
+                if self.thread.as_ref() == Some(&id) {
+                    return false;
+                }
+                self.thread = Some(id);
+                self.thread_input.clear();
+                self.request_thread();
+                true
+            }
+            Msg::CloseThread => {
+                self.thread = None;
+                true
+            }
+            Msg::UpdateThreadInput(value) => {
+                self.thread_input = value;
+                true
+            }
+            Msg::OnThreadKeyPress(e) => {
+                if e.key() == "Enter" {
+                    self.send_thread_reply();
+                    return true;
+                }
+                false
+            }
+            Msg::SubmitThreadReply => {
+                self.send_thread_reply();
+                true
+            }
+            Msg::React { id, emoji } => {
+                self.reaction_picker = None;
+                let conversation = self.current.clone();
+                let me = self.username.clone();
+                let Some(timeline) = self.conversations.get_mut(&conversation) else {
+                    return true;
+                };
+                let add = !timeline.find(&id).is_some_and(|m| m.reacted(&me, &emoji));
+                // Shown right away, the echo from the server changes nothing.
+                if timeline.react(&id, &me, &emoji, add) {
+                    self.persist(&conversation);
+                    self.send_frame(ClientFrame::Reaction {
+                        conversation,
+                        message_id: id,
+                        emoji,
+                        add,
+                    });
+                }
+                true
+            }
+            Msg::DismissPreview { id, url } => {
+                let conversation = self.current.clone();
+                let Some(timeline) = self.conversations.get_mut(&conversation) else {
+                    return false;
+                };
+                let Some(mut previews) = timeline.find(&id).map(|m| m.previews.clone()) else {
+                    return false;
+                };
+                previews.retain(|preview| preview.url != url);
+                // Gone right away, the server confirms with an `unfurl` frame.
+                if timeline.set_previews(&id, &previews) {
+                    self.persist(&conversation);
+                    self.send_frame(ClientFrame::DismissPreview {
+                        conversation,
+                        message_id: id,
+                        url,
+                    });
+                }
+                true
+            }
+            Msg::AttachFiles(files) => {
+                let conversation = self.current.clone();
+                for file in files {
+                    match self.uploads.add(&conversation, file) {
+                        Ok(frame) => {
+                            self.send_frame(frame);
+                        }
+                        Err(rejection) => self.error_message = Some(rejection.to_string()),
+                    }
+                }
+                true
+            }
+            Msg::RemoveUpload(id) => {
+                if let Some(cancel) = self.uploads.remove(&id) {
+                    self.send_frame(cancel);
+                }
+                true
+            }
+            Msg::ChunkRead { upload_id, chunk } => {
+                // Cancelled while reading, or offline: the server asks again
+                // where to carry on once we are back.
+                if !self.uploads.is_uploading(&upload_id) || self.connection != ConnectionState::Connected {
+                    return false;
+                }
+                match chunk {
+                    Ok(chunk) => {
+                        self.send_frame(chunk);
+                        false
+                    }
+                    Err(e) => {
+                        log::error!("Failed to read upload {}: {}", upload_id, e);
+                        self.uploads.fail(&upload_id, e);
+                        self.send_frame(ClientFrame::CancelUpload { upload_id });
+                        true
+                    }
+                }
+            }
+            Msg::Dragging(dragging) => {
+                let changed = self.dragging != dragging;
+                self.dragging = dragging;
+                changed
+            }
+            Msg::TokenRefreshed(token) => {
+                if let Some(session) = self.user.session.borrow_mut().as_mut() {
+                    session.token = token;
+                    session.save();
+                }
+                false
+            }
+            // Unlike a logout, keep the outbox and cache for when the same
+            // user signs back in.
+            Msg::SessionExpired => {
+                self.sign_out(ctx);
+                false
+            }
+            Msg::Logout => {
+                self.send_frame(ClientFrame::Unregister);
+                self.outbox.clear();
+                self.cache.clear();
+                self.sign_out(ctx);
+                false
+            }
+            Msg::Retry(client_id) => {
+                if self.connection == ConnectionState::Connected {
+                    self.transmit(&client_id);
+                } else {
+                    self.outbox.set_status(&client_id, DeliveryStatus::Pending);
+                }
+                true
+            }
+        }
+    }
+
+    fn rendered(&mut self, ctx: &Context<Self>, _first_render: bool) {
+        if let Some(pane) = self.messages_pane() {
+            match std::mem::replace(&mut self.scroll, Scroll::Keep) {
+                Scroll::Keep => {}
+                Scroll::Bottom => pane.set_scroll_top(pane.scroll_height()),
+                Scroll::Anchor { from_bottom } => pane.set_scroll_top(pane.scroll_height() - from_bottom),
+            }
+        }
+
+        // New messages may have become visible.
+        let behind = self
+            .conversations
+            .get(&self.current)
+            .is_some_and(|timeline| timeline.unread > 0);
+        if behind {
+            ctx.link().send_message(Msg::MarkRead);
         }
     }
 
@@ -181,6 +1161,75 @@ impl Component for Chat {
             Msg::UpdateInput(input.value())
         });
         let on_keypress = ctx.link().callback(Msg::OnKeyPress);
+        let on_keydown = ctx.link().callback(Msg::OnKeyDown);
+        let on_paste = ctx.link().batch_callback(|e: web_sys::Event| {
+            let files = pasted_files(&e);
+            if files.is_empty() {
+                return None;
+            }
+            e.prevent_default();
+            Some(Msg::AttachFiles(files))
+        });
+        let on_pick = ctx.link().callback(|e: web_sys::Event| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            let files = file_list(input.files());
+            // So that picking the same file again is a change too.
+            input.set_value("");
+            Msg::AttachFiles(files)
+        });
+        // Dragged text is left to the browser.
+        let on_dragover = ctx.link().batch_callback(|e: DragEvent| {
+            carries_files(&e).then(|| {
+                e.prevent_default();
+                Msg::Dragging(true)
+            })
+        });
+        let on_dragleave = ctx.link().callback(|_: DragEvent| Msg::Dragging(false));
+        let on_drop = ctx.link().batch_callback(|e: DragEvent| {
+            if !carries_files(&e) {
+                return vec![Msg::Dragging(false)];
+            }
+            e.prevent_default();
+            let files = file_list(e.data_transfer().and_then(|data| data.files()));
+            vec![Msg::Dragging(false), Msg::AttachFiles(files)]
+        });
+        let on_room_keypress = ctx.link().callback(Msg::OnRoomKeyPress);
+        let close = ctx.link().callback(|_| Msg::Close);
+
+        let on_scroll = ctx.link().callback(|_: web_sys::Event| Msg::MessagesScrolled);
+
+        let timeline = self.conversations.get(&self.current);
+        let messages = timeline.map(|timeline| timeline.messages.as_slice()).unwrap_or_default();
+        let history_marker = match timeline {
+            Some(timeline) if timeline.loading_history => html! {
+                <div class="text-center text-xs text-cyan-400 font-mono animate-pulse">{"RETRIEVING ARCHIVES..."}</div>
+            },
+            Some(timeline) if !timeline.has_more_history && !messages.is_empty() => html! {
+                <div class="text-center text-xs text-slate-500 font-mono">{"BEGINNING OF TRANSMISSION LOG"}</div>
+            },
+            _ => html! {},
+        };
+        let queued: Vec<_> = self
+            .outbox
+            .entries()
+            .iter()
+            .filter(|entry| entry.conversation == self.current && !entry.in_thread)
+            .collect();
+        let (title, close_label) = match &self.current {
+            Conversation::Room(room) => (format!("# {}", room), "LEAVE"),
+            Conversation::Direct(peer) => (format!("@ {}", peer), "CLOSE"),
+        };
+
+        let (status_dot, status_text, status_label) = match self.connection {
+            ConnectionState::Connected => ("bg-green-400", "text-green-400", "NEURAL LINK ACTIVE".to_string()),
+            ConnectionState::Connecting => ("bg-yellow-400", "text-yellow-400", "ESTABLISHING LINK".to_string()),
+            ConnectionState::Reconnecting { attempt } => (
+                "bg-yellow-400",
+                "text-yellow-400",
+                format!("LINK LOST - RETRY #{}", attempt),
+            ),
+            ConnectionState::Disconnected => ("bg-red-400", "text-red-400", "LINK OFFLINE".to_string()),
+        };
 
         html! {
             <div class="flex w-screen h-screen bg-gray-900 text-gray-100">
@@ -194,44 +1243,44 @@ impl Component for Chat {
                             </h2>
                         </div>
                         <div class="mt-2 flex items-center space-x-2">
-                            <span class="text-sm text-cyan-300/80">{format!("{} nodes active", self.users.len())}</span>
+                            <span class="text-sm text-cyan-300/80">{format!("{} nodes active", self.users.connected())}</span>
                             <div class="w-2 h-2 bg-green-400 rounded-full animate-pulse"></div>
                         </div>
+                        <div class="mt-3 flex items-center justify-between">
+                            <span class="text-xs text-cyan-300/60 truncate">{format!("@{}", self.username)}</span>
+                            {self.view_presence_picker(ctx)}
+                            <button onclick={ctx.link().callback(|_| Msg::Logout)} class="px-3 py-1 text-xs font-medium text-cyan-300 border border-cyan-400/30 rounded-lg hover:bg-cyan-400/10 hover:border-cyan-400/60 transition-all duration-300">
+                                {"LOGOUT"}
+                            </button>
+                        </div>
+                        {self.view_code_preference(ctx)}
                     </div>
                     
                     <div class="overflow-y-auto h-full pb-24 px-4 py-4 space-y-3">
-                        {
-                            self.users.iter().map(|u| {
-                                html!{
-                                    <div class="group p-4 bg-gradient-to-r from-slate-700/50 to-slate-800/50 border border-cyan-500/20 rounded-xl hover:border-cyan-400/50 hover:shadow-lg hover:shadow-cyan-400/10 transition-all duration-300 backdrop-blur-sm hover:scale-[1.02]">
-                                        <div class="flex items-center space-x-3">
-                                            <div class="relative">
-                                                <div class="w-12 h-12 rounded-full bg-gradient-to-r from-cyan-400 to-blue-500 p-0.5">
-                                                    <img class="w-full h-full rounded-full border-2 border-slate-800" 
-                                                         src={u.avatar.clone()} 
-                                                         alt={format!("{}'s neural avatar", u.name)}/>
-                                                </div>
-                                                <div class="absolute -bottom-1 -right-1 w-4 h-4 bg-green-400 border-2 border-slate-800 rounded-full shadow-lg shadow-green-400/50 animate-pulse"></div>
-                                            </div>
-                                            <div class="flex-1 min-w-0">
-                                                <div class="text-sm font-semibold text-cyan-100 truncate group-hover:text-cyan-300 transition-colors">
-                                                    {&u.name}
-                                                </div>
-                                                <div class="text-xs text-green-400 font-medium tracking-wide">
-                                                    {"◉ ONLINE"}
-                                                </div>
-                                            </div>
-                                            <div class="w-2 h-8 bg-gradient-to-t from-cyan-500/20 to-cyan-400/40 rounded-full opacity-60 group-hover:opacity-100 transition-opacity"></div>
-                                        </div>
-                                    </div>
-                                }
-                            }).collect::<Html>()
-                        }
+                        {self.view_rooms(on_room_keypress)}
+                        {self.view_directs()}
+                        {self.users.view(&self.username)}
                     </div>
                 </div>
 
                 // Futuristic main chat area
-                <div class="flex-1 h-full flex flex-col bg-gradient-to-br from-slate-900 via-gray-900 to-slate-800">
+                <div
+                    class="relative flex-1 h-full flex flex-col bg-gradient-to-br from-slate-900 via-gray-900 to-slate-800"
+                    ondragover={on_dragover}
+                    ondragleave={on_dragleave}
+                    ondrop={on_drop}
+                >
+                    {
+                        if self.dragging {
+                            html! {
+                                <div class="absolute inset-0 z-20 flex items-center justify-center bg-slate-900/80 border-2 border-dashed border-cyan-400 rounded-xl pointer-events-none">
+                                    <span class="text-lg font-bold font-mono tracking-widest text-cyan-300">{"DROP FILES TO ATTACH"}</span>
+                                </div>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
                     // Futuristic header
                     <div class="flex-none h-20 bg-gradient-to-r from-slate-800/80 to-slate-700/80 border-b border-cyan-500/30 backdrop-blur-md shadow-lg">
                         <div class="flex items-center justify-between h-full px-8">
@@ -247,12 +1296,26 @@ impl Component for Chat {
                                         {"QUANTUM CHAT NEXUS"}
                                     </h1>
                                     <div class="flex items-center space-x-2 mt-1">
-                                        <div class="w-2 h-2 bg-green-400 rounded-full animate-pulse"></div>
-                                        <span class="text-xs text-green-400 font-semibold tracking-wider">{"NEURAL LINK ACTIVE"}</span>
+                                        <div class={classes!("w-2", "h-2", "rounded-full", "animate-pulse", status_dot)}></div>
+                                        <span class={classes!("text-xs", "font-semibold", "tracking-wider", status_text)}>{status_label}</span>
                                     </div>
                                 </div>
                             </div>
                             <div class="flex items-center space-x-3">
+                                <div class="px-3 py-1 bg-cyan-500/20 border border-cyan-400/30 rounded-full">
+                                    <span class="text-xs text-cyan-300 font-mono">{title}</span>
+                                </div>
+                                {
+                                    if self.current != Conversation::default_room() {
+                                        html! {
+                                            <button onclick={close} class="px-3 py-1 bg-slate-700/60 border border-slate-500/40 rounded-full text-xs text-slate-300 font-mono hover:border-red-400/50 hover:text-red-300 transition-colors">
+                                                {close_label}
+                                            </button>
+                                        }
+                                    } else {
+                                        html! {}
+                                    }
+                                }
                                 <div class="px-3 py-1 bg-cyan-500/20 border border-cyan-400/30 rounded-full">
                                     <span class="text-xs text-cyan-300 font-mono">{"STATUS: SECURE"}</span>
                                 </div>
@@ -272,9 +1335,14 @@ impl Component for Chat {
                     </div>
 
                     // Futuristic messages area
-                    <div class="flex-1 overflow-y-auto p-6 space-y-6 bg-gradient-to-b from-transparent to-slate-900/50">
+                    <div
+                        ref={self.messages_ref.clone()}
+                        onscroll={on_scroll}
+                        class="flex-1 overflow-y-auto p-6 space-y-6 bg-gradient-to-b from-transparent to-slate-900/50"
+                    >
+                        {history_marker}
                         {
-                            if self.messages.is_empty() {
+                            if messages.is_empty() && queued.is_empty() {
                                 html! {
                                     <div class="flex items-center justify-center h-full">
                                         <div class="text-slate-400 text-center max-w-md">
@@ -287,78 +1355,55 @@ impl Component for Chat {
                                     </div>
                                 }
                             } else {
-                                self.messages.iter().map(|m| {
-                                    let user = self.users.iter()
-                                        .find(|u| u.name == m.from)
-                                        .cloned()
-                                        .unwrap_or_else(|| UserProfile {
-                                            name: m.from.clone(),
-                                            avatar: format!(
-                                                "https://avatars.dicebear.com/api/adventurer-neutral/{}.svg",
-                                                m.from
-                                            ),
-                                            is_online: false,
-                                        });
-
-                                    html!{
-                                        <div class="flex items-start space-x-4 max-w-4xl group">
-                                            <div class="flex-shrink-0">
-                                                <div class="w-10 h-10 rounded-full bg-gradient-to-r from-cyan-400 to-blue-500 p-0.5 shadow-lg shadow-cyan-400/30">
-                                                    <img class="w-full h-full rounded-full border border-slate-700" 
-                                                        src={user.avatar} 
-                                                        alt={format!("{}'s neural avatar", user.name)}/>
-                                                </div>
-                                            </div>
-                                            <div class="flex-1 bg-gradient-to-br from-slate-800/60 to-slate-700/40 border border-cyan-500/20 rounded-2xl p-5 backdrop-blur-sm shadow-xl group-hover:border-cyan-400/40 group-hover:shadow-cyan-400/10 transition-all duration-300">
-                                                <div class="flex items-center space-x-3 mb-3">
-                                                    <span class="text-sm font-bold text-cyan-300">{&m.from}</span>
-                                                    <div class="w-1 h-1 bg-cyan-400 rounded-full"></div>
-                                                    <span class="text-xs text-slate-400 font-mono">{"NEURAL_TRANSMISSION"}</span>
-                                                </div>
-                                                <div class="text-gray-100 leading-relaxed">
-                                                    {
-                                                        // Check if the message is a URL to an image
-                                                        if m.message.starts_with("http") && (
-                                                            m.message.ends_with(".jpg") || 
-                                                            m.message.ends_with(".jpeg") || 
-                                                            m.message.ends_with(".png") || 
-                                                            m.message.ends_with(".gif") || 
-                                                            m.message.ends_with(".webp")
-                                                        ) {
-                                                            html! {
-                                                                <img class="mt-3 max-w-sm rounded-xl border border-cyan-500/30 shadow-lg shadow-cyan-400/20" 
-                                                                    src={m.message.clone()} 
-                                                                    alt="Quantum data stream"
-                                                                    loading="lazy"/>
-                                                            }
-                                                        } else {
-                                                            html! {
-                                                                <p>{&m.message}</p>
-                                                            }
-                                                        }
-                                                    }
-                                                </div>
-                                            </div>
-                                        </div>
-                                    }
-                                }).collect::<Html>()
+                                self.view_messages(ctx, timeline, &queued)
+                            }
+                        }
+                        {
+                            if self.new_below > 0 {
+                                let label = match self.new_below {
+                                    1 => "1 new message ↓".to_owned(),
+                                    n => format!("{} new messages ↓", n),
+                                };
+                                html! {
+                                    <div class="sticky bottom-0 flex justify-center pointer-events-none">
+                                        <button
+                                            onclick={ctx.link().callback(|_| Msg::JumpToLatest)}
+                                            class="pointer-events-auto px-4 py-2 bg-cyan-500 text-slate-900 text-xs font-bold font-mono rounded-full shadow-lg shadow-cyan-500/40 hover:bg-cyan-400 transition-colors"
+                                        >
+                                            {label}
+                                        </button>
+                                    </div>
+                                }
+                            } else {
+                                html! {}
                             }
                         }
                     </div>
 
                     // Futuristic input area
                     <div class="flex-none bg-gradient-to-r from-slate-800/90 to-slate-700/90 border-t border-cyan-500/30 p-6 backdrop-blur-md">
+                        {self.typing.view(&self.current)}
+                        {self.view_composer_mode(ctx, timeline)}
+                        {attachments::view_uploads(self.uploads.of(&self.current), &ctx.link().callback(Msg::RemoveUpload))}
                         <div class="flex items-center space-x-4 max-w-6xl mx-auto">
+                            <label title="Attach files" class="p-4 bg-slate-800/80 border border-cyan-500/30 rounded-2xl text-cyan-300 hover:border-cyan-400 hover:bg-cyan-400/10 cursor-pointer transition-all duration-300">
+                                <input type="file" multiple=true class="hidden" onchange={on_pick}/>
+                                <svg viewBox="0 0 24 24" xmlns="http://www.w3.org/2000/svg" class="w-6 h-6 fill-current">
+                                    <path d="M16.5 6v11.5a4 4 0 0 1-8 0V5a2.5 2.5 0 0 1 5 0v10.5a1 1 0 0 1-2 0V6H10v9.5a2.5 2.5 0 0 0 5 0V5a4 4 0 0 0-8 0v12.5a5.5 5.5 0 0 0 11 0V6h-1.5z"></path>
+                                </svg>
+                            </label>
                             <div class="flex-1 relative group">
                                 <div class="absolute inset-0 bg-gradient-to-r from-cyan-500/20 to-blue-500/20 rounded-2xl blur-sm group-focus-within:blur-none transition-all duration-300"></div>
                                 <input 
                                     ref={self.chat_input.clone()}
                                     type="text" 
-                                    placeholder="Transmit neural message..." 
+                                    placeholder={if self.editing.is_some() { "Edit neural message..." } else { "Transmit neural message..." }}
                                     class="relative w-full py-4 px-6 bg-slate-800/80 border border-cyan-500/30 rounded-2xl text-gray-100 placeholder-slate-400 focus:outline-none focus:border-cyan-400 focus:shadow-lg focus:shadow-cyan-400/20 backdrop-blur-sm transition-all duration-300 font-medium"
                                     value={self.input_value.clone()}
                                     oninput={on_input}
                                     onkeypress={on_keypress}
+                                    onkeydown={on_keydown}
+                                    onpaste={on_paste}
                                 />
                                 <div class="absolute right-4 top-1/2 transform -translate-y-1/2 text-slate-500">
                                     <div class="w-2 h-2 bg-cyan-400 rounded-full animate-pulse"></div>
@@ -388,37 +1433,1213 @@ impl Component for Chat {
                         </div>
                     </div>
                 </div>
+                {self.view_thread(ctx)}
             </div>
         }
     }
 }
 
 impl Chat {
+    fn view_rooms(&self, on_keypress: Callback<KeyboardEvent>) -> Html {
+        let mut names: Vec<&String> = self.joined_rooms().chain(self.available_rooms.iter()).collect();
+        names.sort();
+        names.dedup();
+
+        html! {
+            <div class="space-y-2">
+                <div class="text-xs font-mono tracking-widest text-cyan-400/70">{"CHANNELS"}</div>
+                {
+                    names.into_iter().map(|name| {
+                        self.view_conversation_link(Conversation::Room(name.clone()), format!("# {}", name))
+                    }).collect::<Html>()
+                }
+                <input
+                    ref={self.room_input.clone()}
+                    type="text"
+                    placeholder="Join channel..."
+                    class="w-full px-3 py-2 bg-slate-800/80 border border-cyan-500/20 rounded-lg text-sm text-gray-100 placeholder-slate-500 focus:outline-none focus:border-cyan-400 font-mono"
+                    onkeypress={on_keypress}
+                />
+            </div>
+        }
+    }
+
+    fn view_directs(&self) -> Html {
+        let peers: Vec<&String> = self
+            .conversations
+            .keys()
+            .filter_map(|conversation| match conversation {
+                Conversation::Direct(peer) => Some(peer),
+                Conversation::Room(_) => None,
+            })
+            .collect();
+        if peers.is_empty() {
+            return html! {};
+        }
+
+        html! {
+            <div class="space-y-2">
+                <div class="pt-2 text-xs font-mono tracking-widest text-cyan-400/70">{"DIRECT"}</div>
+                {
+                    peers.into_iter().map(|peer| {
+                        self.view_conversation_link(Conversation::Direct(peer.clone()), format!("@ {}", peer))
+                    }).collect::<Html>()
+                }
+            </div>
+        }
+    }
+
+    fn view_conversation_link(&self, conversation: Conversation, label: String) -> Html {
+        let unread = self
+            .conversations
+            .get(&conversation)
+            .map(|timeline| timeline.unread)
+            .unwrap_or(0);
+        let classes = classes!(
+            "flex", "items-center", "justify-between", "px-3", "py-2", "rounded-lg",
+            "border", "text-sm", "font-mono", "transition-all", "duration-300",
+            if conversation == self.current {
+                "bg-cyan-500/20 border-cyan-400/50 text-cyan-200"
+            } else {
+                "bg-slate-700/30 border-cyan-500/10 text-slate-300 hover:border-cyan-400/40"
+            }
+        );
+
+        html! {
+            <Link<Route> to={route_for(&conversation)} classes={classes}>
+                <span class="truncate">{label}</span>
+                {
+                    if unread > 0 {
+                        html! {
+                            <span class="ml-2 px-2 py-0.5 bg-cyan-500 text-slate-900 text-xs font-bold rounded-full">
+                                {unread}
+                            </span>
+                        }
+                    } else {
+                        html! {}
+                    }
+                }
+            </Link<Route>>
+        }
+    }
+
+    /// Small avatars of everyone whose read cursor is at this message.
+    fn view_receipts(&self, timeline: Option<&Timeline>, id: &str) -> Html {
+        let readers: Vec<&String> = timeline
+            .map(|timeline| {
+                timeline
+                    .read_by
+                    .iter()
+                    .filter(|(user, cursor)| cursor.as_str() == id && **user != self.username)
+                    .map(|(user, _)| user)
+                    .collect()
+            })
+            .unwrap_or_default();
+        if readers.is_empty() {
+            return html! {};
+        }
+
+        let title = format!("Seen by {}", readers.iter().map(|u| u.as_str()).collect::<Vec<_>>().join(", "));
+        html! {
+            <div class="flex justify-end items-center mt-1 max-w-4xl space-x-1" {title}>
+                {
+                    readers.into_iter().map(|reader| {
+                        let profile = self.users.get(reader).cloned().unwrap_or_else(|| UserProfile::new(reader));
+                        html! {
+                            <img class="w-4 h-4 rounded-full border border-slate-700" src={profile.avatar} alt={profile.name}/>
+                        }
+                    }).collect::<Html>()
+                }
+            </div>
+        }
+    }
+
+    fn view_presence_picker(&self, ctx: &Context<Self>) -> Html {
+        let onchange = ctx.link().batch_callback(|e: web_sys::Event| {
+            let select: HtmlSelectElement = e.target_unchecked_into();
+            match select.value().as_str() {
+                "online" => Some(Msg::SetPresence(Presence::Online)),
+                "away" => Some(Msg::SetPresence(Presence::Away)),
+                "dnd" => Some(Msg::SetPresence(Presence::DoNotDisturb)),
+                "offline" => Some(Msg::SetPresence(Presence::Offline)),
+                _ => None,
+            }
+        });
+        let option = |value: &'static str, presence: Presence, label: &'static str| {
+            html! { <option {value} selected={self.presence == presence}>{label}</option> }
+        };
+
+        html! {
+            <select {onchange} title={status_label(self.presence())} class="px-2 py-1 text-xs bg-slate-800 text-cyan-300 border border-cyan-400/30 rounded-lg focus:outline-none focus:border-cyan-400/60">
+                {option("online", Presence::Online, "Online")}
+                {option("away", Presence::Away, "Away")}
+                {option("dnd", Presence::DoNotDisturb, "Do not disturb")}
+                {option("offline", Presence::Offline, "Invisible")}
+            </select>
+        }
+    }
+
+    fn view_code_preference(&self, ctx: &Context<Self>) -> Html {
+        let onchange = ctx.link().batch_callback(|e: web_sys::Event| {
+            let select: HtmlSelectElement = e.target_unchecked_into();
+            select.value().parse().ok().map(Msg::SetCollapseCodeAfter)
+        });
+        let current = self.collapse_code_after();
+
+        html! {
+            <label class="mt-3 flex items-center justify-between text-xs text-cyan-300/60">
+                {"Collapse code after"}
+                <select {onchange} class="px-2 py-1 text-xs bg-slate-800 text-cyan-300 border border-cyan-400/30 rounded-lg focus:outline-none focus:border-cyan-400/60">
+                    {
+                        COLLAPSE_CHOICES.iter().map(|&lines| html! {
+                            <option value={lines.to_string()} selected={lines == current}>{format!("{} lines", lines)}</option>
+                        }).collect::<Html>()
+                    }
+                </select>
+            </label>
+        }
+    }
+
+    fn joined_rooms(&self) -> impl Iterator<Item = &String> {
+        self.conversations.keys().filter_map(|conversation| match conversation {
+            Conversation::Room(room) => Some(room),
+            Conversation::Direct(_) => None,
+        })
+    }
+
+    /// The open conversation: day separators, then messages grouped by
+    /// sender, then whatever is still queued in the outbox.
+    fn view_messages(&self, ctx: &Context<Self>, timeline: Option<&Timeline>, queued: &[&OutgoingMessage]) -> Html {
+        let messages = timeline.map(|timeline| timeline.messages.as_slice()).unwrap_or_default();
+        let divider_after = timeline.and_then(|timeline| timeline.divider_after.as_deref());
+        let mut items = Vec::with_capacity(messages.len() + queued.len());
+        // Sender and time of the message above, to group by.
+        let mut previous: Option<(&str, Option<f64>)> = None;
+
+        for m in messages {
+            let sent_at = m.timestamp.as_deref().and_then(time::parse);
+            if let Some(sent_at) = sent_at {
+                let new_day = match previous {
+                    Some((_, Some(before))) => !time::same_day(before, sent_at),
+                    _ => true,
+                };
+                if new_day {
+                    items.push(view_day_separator(sent_at));
+                    previous = None;
+                }
+            }
+            let continued = match previous {
+                Some((from, Some(before))) => {
+                    from == m.from && sent_at.is_some_and(|sent_at| sent_at - before < GROUP_WINDOW_MS)
+                }
+                Some((from, None)) => from == m.from && sent_at.is_none(),
+                None => false,
+            };
+            previous = Some((&m.from, sent_at));
+
+            let message = self.view_message(Bubble {
+                from: &m.from,
+                body: &m.message,
+                sent_at,
+                continued,
+                deleted: m.deleted,
+                status: html! {},
+                menu: self.view_message_menu(ctx, m, false),
+                edits: self.view_edits(ctx, m),
+                reactions: self.view_reactions(ctx, m),
+                quote: self.view_quote(ctx, timeline, m.reply_to.as_deref()),
+                replies: view_reply_count(ctx, m),
+                previews: self.view_previews(ctx, m),
+                attachments: attachments::view_attachments(&m.attachments),
+            });
+            let Some(id) = m.id.as_deref() else {
+                items.push(message);
+                continue;
+            };
+            items.push(html! {
+                <div data-message-id={id.to_owned()}>
+                    {message}
+                    {self.view_receipts(timeline, id)}
+                    {
+                        if divider_after == Some(id) {
+                            html! {
+                                <div class="flex items-center space-x-3 mt-6 max-w-4xl">
+                                    <div class="flex-1 h-px bg-red-400/50"></div>
+                                    <span class="text-xs text-red-300 font-mono tracking-widest">{"NEW MESSAGES"}</span>
+                                    <div class="flex-1 h-px bg-red-400/50"></div>
+                                </div>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                </div>
+            });
+        }
+
+        for entry in queued {
+            let continued = previous.is_some_and(|(from, _)| from == self.username);
+            previous = Some((&self.username, None));
+            items.push(self.view_message(Bubble {
+                continued,
+                quote: self.view_quote(ctx, timeline, entry.reply_to.as_deref()),
+                ..self.queued_bubble(ctx, entry)
+            }));
+        }
+
+        items.into_iter().collect()
+    }
+
+    /// A message still in the outbox, with how far it got.
+    fn queued_bubble<'a>(&'a self, ctx: &Context<Self>, entry: &'a OutgoingMessage) -> Bubble<'a> {
+        let status = match entry.status {
+            DeliveryStatus::Pending => html! {
+                <span class="text-xs text-yellow-400 font-mono">{"QUEUED"}</span>
+            },
+            DeliveryStatus::Sent => html! {
+                <span class="text-xs text-cyan-400 font-mono animate-pulse">{"TRANSMITTING"}</span>
+            },
+            DeliveryStatus::Failed => {
+                let client_id = entry.client_id.clone();
+                let retry = ctx.link().callback(move |_| Msg::Retry(client_id.clone()));
+                html! {
+                    <button onclick={retry} class="text-xs text-red-400 hover:text-red-300 font-mono underline">
+                        {"FAILED - RETRY"}
+                    </button>
+                }
+            }
+        };
+        Bubble {
+            from: &self.username,
+            body: &entry.text,
+            sent_at: None,
+            continued: false,
+            deleted: false,
+            status,
+            menu: html! {},
+            edits: html! {},
+            reactions: html! {},
+            quote: html! {},
+            replies: html! {},
+            previews: html! {},
+            attachments: attachments::view_attachments(&entry.attachments),
+        }
+    }
+
+    fn view_message(&self, bubble: Bubble) -> Html {
+        let Bubble {
+            from,
+            body,
+            sent_at,
+            continued,
+            deleted,
+            status,
+            menu,
+            edits,
+            reactions,
+            quote,
+            replies,
+            previews,
+            attachments,
+        } = bubble;
+        let user = self
+            .users
+            .get(from)
+            .cloned()
+            .unwrap_or_else(|| UserProfile::new(from));
+        let sent = match sent_at {
+            Some(sent_at) => html! {
+                <span class="text-xs text-slate-400 font-mono" title={time::full(sent_at)}>{time::message_time(sent_at)}</span>
+            },
+            None => html! {},
+        };
+        let body = if deleted {
+            html! { <p class="italic text-slate-500">{"Message deleted"}</p> }
+        } else {
+            view_body(body, self.collapse_code_after())
+        };
+
+        // Follow-ups from the same sender drop the avatar and the header,
+        // keeping only the time on hover.
+        if continued {
+            return html! {
+                <div class="flex items-start space-x-4 max-w-4xl group -mt-4">
+                    <div class="flex-shrink-0 w-10 pt-3 text-right opacity-0 group-hover:opacity-100 transition-opacity">
+                        {sent}
+                    </div>
+                    <div class="relative flex-1 bg-gradient-to-br from-slate-800/60 to-slate-700/40 border border-cyan-500/20 rounded-2xl px-5 py-3 backdrop-blur-sm shadow-xl group-hover:border-cyan-400/40 group-hover:shadow-cyan-400/10 transition-all duration-300">
+                        {menu}
+                        {quote}
+                        <div class="text-gray-100 leading-relaxed">
+                            {body}
+                        </div>
+                        {attachments}
+                        {previews}
+                        {edits}
+                        {reactions}
+                        {replies}
+                        {status}
+                    </div>
+                </div>
+            };
+        }
+
+        html!{
+            <div class="flex items-start space-x-4 max-w-4xl group">
+                <div class="flex-shrink-0">
+                    <div class="w-10 h-10 rounded-full bg-gradient-to-r from-cyan-400 to-blue-500 p-0.5 shadow-lg shadow-cyan-400/30">
+                        <img class="w-full h-full rounded-full border border-slate-700" 
+                            src={user.avatar} 
+                            alt={format!("{}'s neural avatar", user.name)}/>
+                    </div>
+                </div>
+                <div class="relative flex-1 bg-gradient-to-br from-slate-800/60 to-slate-700/40 border border-cyan-500/20 rounded-2xl p-5 backdrop-blur-sm shadow-xl group-hover:border-cyan-400/40 group-hover:shadow-cyan-400/10 transition-all duration-300">
+                    {menu}
+                    <div class="flex items-center space-x-3 mb-3">
+                        <span class="text-sm font-bold text-cyan-300">{from}</span>
+                        <div class="w-1 h-1 bg-cyan-400 rounded-full"></div>
+                        {sent}
+                        {status}
+                    </div>
+                    {quote}
+                    <div class="text-gray-100 leading-relaxed">
+                        {body}
+                    </div>
+                    {attachments}
+                    {previews}
+                    {edits}
+                    {reactions}
+                    {replies}
+                </div>
+            </div>
+        }
+    }
+
+    /// React, reply and open the thread on any message, edit and delete on
+    /// our own. Inside the thread panel there is nothing to reply to but the
+    /// thread itself.
+    fn view_message_menu(&self, ctx: &Context<Self>, message: &ChatMessage, in_panel: bool) -> Html {
+        let Some(id) = message.id.clone() else {
+            return html! {};
+        };
+        if message.deleted {
+            return html! {};
+        }
+        let react = {
+            let id = id.clone();
+            ctx.link().callback(move |_| Msg::ToggleReactionPicker(id.clone()))
+        };
+        let reply = if in_panel {
+            html! {}
+        } else {
+            let reply = {
+                let id = id.clone();
+                ctx.link().callback(move |_| Msg::ReplyTo(id.clone()))
+            };
+            let thread = {
+                let id = id.clone();
+                ctx.link().callback(move |_| Msg::OpenThread(id.clone()))
+            };
+            html! {
+                <>
+                    <button onclick={reply} class="px-2 py-1 text-cyan-300 hover:bg-cyan-400/10">{"REPLY"}</button>
+                    <button onclick={thread} class="px-2 py-1 text-cyan-300 hover:bg-cyan-400/10">{"THREAD"}</button>
+                </>
+            }
+        };
+        let own = if message.from == self.username {
+            let edit = {
+                let id = id.clone();
+                ctx.link().callback(move |_| Msg::StartEdit(id.clone()))
+            };
+            let delete = ctx.link().callback(move |_| Msg::DeleteMessage(id.clone()));
+            html! {
+                <>
+                    <button onclick={edit} class="px-2 py-1 text-cyan-300 hover:bg-cyan-400/10">{"EDIT"}</button>
+                    <button onclick={delete} class="px-2 py-1 text-red-300 hover:bg-red-400/10">{"DELETE"}</button>
+                </>
+            }
+        } else {
+            html! {}
+        };
+
+        html! {
+            <div class="absolute -top-3 right-4 hidden group-hover:flex bg-slate-800 border border-cyan-500/30 rounded-lg shadow-lg overflow-hidden text-xs font-mono">
+                <button onclick={react} class="px-2 py-1 text-cyan-300 hover:bg-cyan-400/10">{"REACT"}</button>
+                {reply}
+                {own}
+            </div>
+        }
+    }
+
+    /// The reaction chips, and the picker when it is open.
+    fn view_reactions(&self, ctx: &Context<Self>, message: &ChatMessage) -> Html {
+        let Some(id) = message.id.clone() else {
+            return html! {};
+        };
+        let picking = self.reaction_picker.as_ref() == Some(&id);
+        let react = ctx.link().callback(move |emoji| Msg::React { id: id.clone(), emoji });
+
+        html! {
+            <>
+                {reactions::view_chips(&message.reactions, &self.username, &react)}
+                {
+                    if picking && !message.deleted {
+                        reactions::view_picker(&react)
+                    } else {
+                        html! {}
+                    }
+                }
+            </>
+        }
+    }
+
+    /// The link previews of a message, which its sender may dismiss.
+    fn view_previews(&self, ctx: &Context<Self>, message: &ChatMessage) -> Html {
+        let Some(id) = message.id.clone() else {
+            return html! {};
+        };
+        if message.deleted {
+            return html! {};
+        }
+        let dismiss = (message.from == self.username)
+            .then(|| ctx.link().callback(move |url| Msg::DismissPreview { id: id.clone(), url }));
+        previews::view_cards(&message.previews, dismiss.as_ref())
+    }
+
+    /// "(edited)", which unfolds the earlier versions when clicked.
+    fn view_edits(&self, ctx: &Context<Self>, message: &ChatMessage) -> Html {
+        let Some(id) = message.id.clone() else {
+            return html! {};
+        };
+        if !message.edited() || message.deleted {
+            return html! {};
+        }
+        let title = message
+            .edited_at
+            .as_deref()
+            .and_then(time::parse)
+            .map(|at| format!("Edited {}", time::full(at)));
+        let expanded = self.showing_edits.as_ref() == Some(&id);
+        let toggle = ctx.link().callback(move |_| Msg::ToggleEdits(id.clone()));
+
+        html! {
+            <div class="mt-1">
+                <button onclick={toggle} {title} class="text-xs text-slate-500 hover:text-cyan-300 font-mono">
+                    {"(edited)"}
+                </button>
+                {
+                    if expanded {
+                        html! {
+                            <ol class="mt-2 pl-3 space-y-1 border-l border-slate-600">
+                                {
+                                    message.edits.iter().map(|revision| {
+                                        let written = revision.timestamp.as_deref().and_then(time::parse);
+                                        html! {
+                                            <li class="text-sm text-slate-400">
+                                                {
+                                                    match written {
+                                                        Some(written) => html! {
+                                                            <span class="mr-2 text-xs text-slate-500 font-mono" title={time::full(written)}>
+                                                                {time::message_time(written)}
+                                                            </span>
+                                                        },
+                                                        None => html! {},
+                                                    }
+                                                }
+                                                {&revision.message}
+                                            </li>
+                                        }
+                                    }).collect::<Html>()
+                                }
+                            </ol>
+                        }
+                    } else {
+                        html! {}
+                    }
+                }
+            </div>
+        }
+    }
+
+    /// The start of the message a reply answers, opening its thread.
+    fn view_quote(&self, ctx: &Context<Self>, timeline: Option<&Timeline>, reply_to: Option<&str>) -> Html {
+        let Some(parent) = reply_to else {
+            return html! {};
+        };
+        let (from, text) = match timeline.and_then(|timeline| timeline.find(parent)) {
+            Some(message) if message.deleted => (message.from.clone(), "Message deleted".to_owned()),
+            Some(message) => match message.attachments.first() {
+                Some(attachment) if message.message.trim().is_empty() => {
+                    (message.from.clone(), format!("📎 {}", attachment.name))
+                }
+                _ => (message.from.clone(), snippet(&message.message)),
+            },
+            None => (String::new(), "Original message not loaded".to_owned()),
+        };
+        let parent = parent.to_owned();
+        let open = ctx.link().callback(move |_| Msg::OpenThread(parent.clone()));
+
+        html! {
+            <button onclick={open} class="block w-full mb-2 pl-3 border-l-2 border-cyan-400/50 text-left text-xs text-slate-400 hover:text-slate-300 truncate">
+                <span class="mr-2 font-semibold text-cyan-300/80">{from}</span>
+                {text}
+            </button>
+        }
+    }
+
+    /// What the composer is doing besides writing a new message.
+    fn view_composer_mode(&self, ctx: &Context<Self>, timeline: Option<&Timeline>) -> Html {
+        let (label, cancel) = if self.editing.is_some() {
+            ("EDITING MESSAGE - ENTER TO SAVE, ESC TO CANCEL".to_owned(), Msg::CancelEdit)
+        } else if let Some(parent) = &self.replying_to {
+            let label = match timeline.and_then(|timeline| timeline.find(parent)) {
+                Some(message) if !message.deleted => {
+                    format!("REPLYING TO {}: {}", message.from.to_uppercase(), snippet(&message.message))
+                }
+                _ => "REPLYING".to_owned(),
+            };
+            (label, Msg::CancelReply)
+        } else {
+            return html! {};
+        };
+        let onclick = ctx.link().callback_once(move |_| cancel);
+
+        html! {
+            <div class="flex items-center justify-between max-w-6xl mx-auto mb-2 text-xs font-mono text-amber-300">
+                <span class="truncate">{label}</span>
+                <button {onclick} class="ml-4 hover:text-amber-200 underline">{"CANCEL"}</button>
+            </div>
+        }
+    }
+
+    /// The side panel with the open thread: the message, its replies and a
+    /// composer for more.
+    fn view_thread(&self, ctx: &Context<Self>) -> Html {
+        let Some(parent_id) = &self.thread else {
+            return html! {};
+        };
+        let timeline = self.conversations.get(&self.current);
+        let parent = timeline.and_then(|timeline| timeline.find(parent_id));
+        let replies = timeline.and_then(|timeline| timeline.threads.get(parent_id));
+        let queued = self
+            .outbox
+            .entries()
+            .iter()
+            .filter(|entry| entry.in_thread && entry.reply_to.as_ref() == Some(parent_id));
+        let on_input = ctx.link().callback(|e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            Msg::UpdateThreadInput(input.value())
+        });
+
+        html! {
+            <div class="flex-none w-96 h-full flex flex-col bg-gradient-to-b from-slate-800 to-slate-900 border-l border-cyan-500/30 shadow-2xl">
+                <div class="flex-none h-20 flex items-center justify-between px-6 border-b border-cyan-500/30">
+                    <span class="text-sm font-bold font-mono tracking-widest text-cyan-300">{"THREAD"}</span>
+                    <button onclick={ctx.link().callback(|_| Msg::CloseThread)} class="px-3 py-1 bg-slate-700/60 border border-slate-500/40 rounded-full text-xs text-slate-300 font-mono hover:border-red-400/50 hover:text-red-300 transition-colors">
+                        {"CLOSE"}
+                    </button>
+                </div>
+                <div class="flex-1 overflow-y-auto p-4 space-y-6">
+                    {
+                        match parent {
+                            Some(parent) => self.view_thread_message(ctx, parent),
+                            None => html! {
+                                <div class="text-center text-xs text-slate-500 font-mono">{"ORIGINAL MESSAGE NOT LOADED"}</div>
+                            },
+                        }
+                    }
+                    {
+                        match replies {
+                            Some(replies) => html! {
+                                <>
+                                    <div class="flex items-center space-x-3">
+                                        <span class="text-xs text-slate-400 font-mono tracking-widest">
+                                            {if replies.len() == 1 { "1 REPLY".to_owned() } else { format!("{} REPLIES", replies.len()) }}
+                                        </span>
+                                        <div class="flex-1 h-px bg-slate-600/60"></div>
+                                    </div>
+                                    { replies.iter().map(|reply| self.view_thread_message(ctx, reply)).collect::<Html>() }
+                                </>
+                            },
+                            None => html! {
+                                <div class="text-center text-xs text-cyan-400 font-mono animate-pulse">{"RETRIEVING REPLIES..."}</div>
+                            },
+                        }
+                    }
+                    { queued.map(|entry| self.view_message(self.queued_bubble(ctx, entry))).collect::<Html>() }
+                </div>
+                <div class="flex-none flex items-center space-x-2 p-4 border-t border-cyan-500/30">
+                    <input
+                        ref={self.thread_input_ref.clone()}
+                        type="text"
+                        placeholder="Reply in thread..."
+                        class="flex-1 py-3 px-4 bg-slate-800/80 border border-cyan-500/30 rounded-xl text-sm text-gray-100 placeholder-slate-400 focus:outline-none focus:border-cyan-400"
+                        value={self.thread_input.clone()}
+                        oninput={on_input}
+                        onkeypress={ctx.link().callback(Msg::OnThreadKeyPress)}
+                    />
+                    <button onclick={ctx.link().callback(|_| Msg::SubmitThreadReply)} class="px-4 py-3 bg-gradient-to-r from-cyan-500 to-blue-600 hover:from-cyan-400 hover:to-blue-500 text-white text-xs font-bold font-mono rounded-xl shadow-lg shadow-cyan-500/30">
+                        {"SEND"}
+                    </button>
+                </div>
+            </div>
+        }
+    }
+
+    fn view_thread_message(&self, ctx: &Context<Self>, message: &ChatMessage) -> Html {
+        self.view_message(Bubble {
+            from: &message.from,
+            body: &message.message,
+            sent_at: message.timestamp.as_deref().and_then(time::parse),
+            continued: false,
+            deleted: message.deleted,
+            status: html! {},
+            menu: self.view_message_menu(ctx, message, true),
+            edits: self.view_edits(ctx, message),
+            reactions: self.view_reactions(ctx, message),
+            quote: html! {},
+            replies: html! {},
+            previews: self.view_previews(ctx, message),
+            attachments: attachments::view_attachments(&message.attachments),
+        })
+    }
+
     fn send_message(&mut self) {
-        if self.input_value.trim().is_empty() {
+        let conversation = self.current.clone();
+        let has_files = self.editing.is_none() && self.uploads.of(&conversation).next().is_some();
+        if self.input_value.trim().is_empty() && !has_files {
             return;
         }
-        
-        let message = WebSocketMessage {
-            message_type: MsgTypes::Message,
-            data: Some(self.input_value.clone()),
-            data_array: None,
-        };
-
-        match serde_json::to_string(&message) {
-            Ok(json) => {
-                let _ = self.wss.tx.clone().try_send(json);
-                self.input_value.clear();
-                
-                // Clear the input field
-                if let Some(input) = self.chat_input.cast::<HtmlInputElement>() {
-                    input.set_value("");
-                }
+        if let Some(id) = self.editing.take() {
+            self.send_edit(id);
+            return;
+        }
+        if self.uploads.pending(&conversation) {
+            self.error_message = Some("Wait for the uploads to finish".to_owned());
+            return;
+        }
+        let attachments = self.uploads.take_done(&conversation);
+        if self.input_value.trim().is_empty() && attachments.is_empty() {
+            // Only failed uploads, nothing to send.
+            return;
+        }
+
+        let text = self.input_value.clone();
+        let reply_to = self.replying_to.take();
+        self.queue_message(text, reply_to, false, attachments);
+
+        self.input_value.clear();
+        self.stop_typing();
+        self.scroll = Scroll::Bottom;
+        self.timeline(&self.current.clone()).divider_after = None;
+
+        // Clear the input field
+        if let Some(input) = self.chat_input.cast::<HtmlInputElement>() {
+            input.set_value("");
+        }
+    }
+
+    fn send_thread_reply(&mut self) {
+        let Some(parent) = self.thread.clone() else {
+            return;
+        };
+        if self.thread_input.trim().is_empty() {
+            return;
+        }
+        let text = std::mem::take(&mut self.thread_input);
+        self.queue_message(text, Some(parent), true, vec![]);
+        if let Some(input) = self.thread_input_ref.cast::<HtmlInputElement>() {
+            input.set_value("");
+        }
+    }
+
+    /// Puts a message for the open conversation in the outbox, sending it
+    /// right away when connected.
+    fn queue_message(&mut self, text: String, reply_to: Option<String>, in_thread: bool, attachments: Vec<Attachment>) {
+        let client_id = self
+            .outbox
+            .push(&self.current, text, reply_to, in_thread, attachments)
+            .client_id
+            .clone();
+        if self.connection == ConnectionState::Connected {
+            self.transmit(&client_id);
+        }
+    }
+
+    /// Sends the composer as the new text of message `id`. The timeline
+    /// changes once the server echoes the edit.
+    fn send_edit(&mut self, id: String) {
+        let text = std::mem::take(&mut self.input_value);
+        let unchanged = self
+            .conversations
+            .get(&self.current)
+            .and_then(|timeline| timeline.find(&id))
+            .is_none_or(|message| message.message == text);
+        if !unchanged {
+            self.send_frame(ClientFrame::Edit {
+                conversation: self.current.clone(),
+                message_id: id,
+                text,
+            });
+        }
+        self.stop_typing();
+        if let Some(input) = self.chat_input.cast::<HtmlInputElement>() {
+            input.set_value("");
+        }
+    }
+
+    /// Puts the text of our message `id` into the composer for editing.
+    fn start_edit(&mut self, id: &str) -> bool {
+        let text = match self.conversations.get(&self.current).and_then(|timeline| timeline.find(id)) {
+            Some(message) if message.from == self.username && !message.deleted => message.message.clone(),
+            _ => return false,
+        };
+        self.replying_to = None;
+        self.editing = Some(id.to_owned());
+        self.input_value = text;
+        if let Some(input) = self.chat_input.cast::<HtmlInputElement>() {
+            input.set_value(&self.input_value);
+            let _ = input.focus();
+        }
+        true
+    }
+
+    fn cancel_edit(&mut self) {
+        if self.editing.take().is_some() {
+            self.input_value.clear();
+            self.stop_typing();
+            if let Some(input) = self.chat_input.cast::<HtmlInputElement>() {
+                input.set_value("");
+            }
+        }
+    }
+
+    /// Re-sends everything the server has not acknowledged yet.
+    fn flush_outbox(&mut self) {
+        let ids: Vec<String> = self.outbox.entries().iter().map(|e| e.client_id.clone()).collect();
+        for client_id in ids {
+            self.transmit(&client_id);
+        }
+    }
+
+    fn transmit(&mut self, client_id: &str) {
+        let frame = match self.outbox.entries().iter().find(|e| e.client_id == client_id) {
+            Some(entry) => match &entry.conversation {
+                Conversation::Room(room) => ClientFrame::Message {
+                    text: entry.text.clone(),
+                    client_id: entry.client_id.clone(),
+                    room: room.clone(),
+                    reply_to: entry.reply_to.clone(),
+                    in_thread: entry.in_thread,
+                    attachments: entry.attachments.iter().map(|a| a.id.clone()).collect(),
+                },
+                Conversation::Direct(peer) => ClientFrame::Direct {
+                    to: peer.clone(),
+                    text: entry.text.clone(),
+                    client_id: entry.client_id.clone(),
+                    reply_to: entry.reply_to.clone(),
+                    in_thread: entry.in_thread,
+                    attachments: entry.attachments.iter().map(|a| a.id.clone()).collect(),
+                },
             },
+            None => return,
+        };
+
+        let status = if self.send_frame(frame) {
+            DeliveryStatus::Sent
+        } else {
+            DeliveryStatus::Failed
+        };
+        self.outbox.set_status(client_id, status);
+    }
+
+    /// The timeline of `conversation`, seeded from the local cache the first
+    /// time it is opened.
+    fn timeline(&mut self, conversation: &Conversation) -> &mut Timeline {
+        self.conversations
+            .entry(conversation.clone())
+            .or_insert_with(|| Timeline::cached(self.cache.load(conversation)))
+    }
+
+    fn persist(&self, conversation: &Conversation) {
+        if let Some(timeline) = self.conversations.get(conversation) {
+            self.cache.save(conversation, &timeline.messages);
+        }
+    }
+
+    fn messages_pane(&self) -> Option<web_sys::Element> {
+        self.messages_ref.cast::<web_sys::Element>()
+    }
+
+    /// Sticks to the bottom when the user was reading the latest messages or
+    /// wrote this one, counts it towards the "new messages" button otherwise.
+    fn follow_new_message(&mut self, from_me: bool) {
+        if from_me || self.messages_pane().as_ref().is_none_or(is_at_bottom) {
+            self.scroll = Scroll::Bottom;
+        } else {
+            self.new_below += 1;
+        }
+    }
+
+    /// Advances the read cursor of the open conversation to the newest
+    /// message fully in view, telling the server. Nothing counts as seen
+    /// while the tab is hidden or the user is away.
+    fn mark_read(&mut self) -> bool {
+        if self.idle || document().hidden() {
+            return false;
+        }
+        let pane = self.messages_pane();
+        let timeline = match self.conversations.get_mut(&self.current) {
+            Some(timeline) => timeline,
+            None => return false,
+        };
+        // Messages from legacy servers have no ids to point a cursor at,
+        // having the conversation open has to do.
+        if timeline.messages.iter().all(|m| m.id.is_none()) {
+            return std::mem::take(&mut timeline.unread) > 0;
+        }
+
+        let Some(seen) = pane.and_then(|pane| newest_visible(&pane)) else {
+            return false;
+        };
+        if !timeline.advance_cursor(&seen, &self.username) {
+            return false;
+        }
+        self.send_frame(ClientFrame::Read {
+            conversation: self.current.clone(),
+            message_id: seen,
+        });
+        true
+    }
+
+    fn stop_typing(&mut self) {
+        self.typing_idle = None;
+        if let Some(frame) = self.typing_notifier.stop() {
+            self.send_frame(frame);
+        }
+    }
+
+    /// Drops expired typists and wakes up again when the next one expires.
+    fn schedule_typing_expiry(&mut self, ctx: &Context<Self>) {
+        self.typing_expiry = self.typing.expire().map(|in_ms| {
+            let link = ctx.link().clone();
+            Timeout::new(in_ms.ceil() as u32, move || link.send_message(Msg::TypingExpired))
+        });
+    }
+
+    /// What others get to see: away while idle, unless the user picked
+    /// something other than online.
+    fn presence(&self) -> Presence {
+        match self.presence {
+            Presence::Online if self.idle => Presence::Away,
+            presence => presence,
+        }
+    }
+
+    /// Tells the server when [`Chat::presence`] moved away from `before`.
+    fn presence_changed(&mut self, before: Presence) -> bool {
+        let presence = self.presence();
+        if presence == before {
+            return false;
+        }
+        if self.connection == ConnectionState::Connected {
+            self.send_frame(ClientFrame::Presence { status: presence });
+        }
+        self.users.update(&self.username, presence, None);
+        true
+    }
+
+    fn sign_out(&self, ctx: &Context<Self>) {
+        Session::clear();
+        *self.user.session.borrow_mut() = None;
+        if let Some(history) = ctx.link().history() {
+            history.push(Route::Login);
+        }
+    }
+
+    fn collapse_code_after(&self) -> usize {
+        self.user
+            .session
+            .borrow()
+            .as_ref()
+            .and_then(|session| session.preferences.collapse_code_after)
+            .unwrap_or(DEFAULT_COLLAPSE_AFTER)
+    }
+
+    fn remember_conversation(&self) {
+        if let Some(session) = self.user.session.borrow_mut().as_mut() {
+            session.preferences.last_conversation = Some(self.current.clone());
+            session.save();
+        }
+    }
+
+    /// Asks for the replies of the open thread.
+    fn request_thread(&mut self) {
+        if let Some(parent) = self.thread.clone() {
+            self.send_frame(ClientFrame::Thread {
+                conversation: self.current.clone(),
+                message_id: parent,
+            });
+        }
+    }
+
+    fn request_history(&mut self, conversation: Conversation, before: Option<String>) {
+        let frame = ClientFrame::History {
+            conversation: conversation.clone(),
+            before,
+            limit: HISTORY_PAGE_SIZE,
+        };
+        if self.send_frame(frame) {
+            self.timeline(&conversation).loading_history = true;
+        }
+    }
+
+    /// Asks for the latest page of `conversation` unless it already arrived
+    /// on this connection.
+    fn request_latest(&mut self, conversation: &Conversation) {
+        let loaded = self.conversations.get(conversation).is_some_and(|t| t.latest_loaded);
+        if !loaded && self.connection == ConnectionState::Connected {
+            self.request_history(conversation.clone(), None);
+        }
+    }
+
+    fn send_frame(&mut self, frame: impl Into<Outgoing>) -> bool {
+        match self.wss.tx.try_send(frame.into()) {
+            Ok(()) => true,
             Err(e) => {
-                log::error!("Failed to serialize message: {:?}", e);
-                self.error_message = Some(format!("Failed to send message: {}", e));
+                log::error!("Failed to queue frame: {:?}", e);
+                false
             }
         }
     }
-}
\ No newline at end of file
+}
+
+fn route_for(conversation: &Conversation) -> Route {
+    match conversation {
+        Conversation::Room(room) => Route::ChatRoom { room: room.clone() },
+        Conversation::Direct(peer) => Route::Dm {
+            username: peer.clone(),
+        },
+    }
+}
+
+fn is_at_bottom(pane: &web_sys::Element) -> bool {
+    pane.scroll_height() - pane.scroll_top() - pane.client_height() <= STICK_TO_BOTTOM_THRESHOLD
+}
+
+/// Id of the newest message whose bottom edge is inside the messages pane.
+fn newest_visible(pane: &web_sys::Element) -> Option<String> {
+    let view = pane.get_bounding_client_rect();
+    let messages = pane.query_selector_all("[data-message-id]").ok()?;
+    (0..messages.length())
+        .rev()
+        .filter_map(|index| messages.item(index)?.dyn_into::<web_sys::Element>().ok())
+        .find(|message| {
+            let rect = message.get_bounding_client_rect();
+            rect.bottom() <= view.bottom() + 1.0 && rect.bottom() > view.top()
+        })
+        .and_then(|message| message.get_attribute("data-message-id"))
+}
+
+/// "3 REPLIES" under a message that has any, opening the thread.
+fn view_reply_count(ctx: &Context<Chat>, message: &ChatMessage) -> Html {
+    let (Some(id), count) = (message.id.clone(), message.reply_count) else {
+        return html! {};
+    };
+    if count == 0 {
+        return html! {};
+    }
+    let open = ctx.link().callback(move |_| Msg::OpenThread(id.clone()));
+    let label = if count == 1 { "1 REPLY".to_owned() } else { format!("{} REPLIES", count) };
+
+    html! {
+        <button onclick={open} class="mt-2 text-xs font-mono text-cyan-400 hover:text-cyan-300 hover:underline">
+            {label}
+        </button>
+    }
+}
+
+/// The first line of `text`, cut short for quotes.
+fn snippet(text: &str) -> String {
+    let line = text.lines().next().unwrap_or_default();
+    if line.chars().count() > QUOTE_LENGTH {
+        format!("{}…", line.chars().take(QUOTE_LENGTH).collect::<String>())
+    } else {
+        line.to_owned()
+    }
+}
+
+/// The files in `list`, e.g. of a file input or a drop.
+fn file_list(list: Option<FileList>) -> Vec<File> {
+    let Some(list) = list else {
+        return vec![];
+    };
+    (0..list.length())
+        .filter_map(|index| list.get(index))
+        .map(File::from)
+        .collect()
+}
+
+/// Files pasted into the composer. `clipboardData` is read through
+/// `Reflect`, web-sys only has `ClipboardEvent` behind its unstable APIs.
+fn pasted_files(e: &web_sys::Event) -> Vec<File> {
+    let data = js_sys::Reflect::get(e, &"clipboardData".into())
+        .ok()
+        .and_then(|data| data.dyn_into::<DataTransfer>().ok());
+    file_list(data.and_then(|data| data.files()))
+}
+
+/// Whether files, rather than text or a link, are being dragged.
+fn carries_files(e: &DragEvent) -> bool {
+    e.data_transfer()
+        .is_some_and(|data| data.types().includes(&"Files".into(), 0))
+}
+
+fn view_day_separator(day: f64) -> Html {
+    html! {
+        <div class="flex items-center space-x-3 max-w-4xl">
+            <div class="flex-1 h-px bg-slate-600/60"></div>
+            <span class="text-xs text-slate-400 font-mono tracking-widest uppercase">{time::day_label(day)}</span>
+            <div class="flex-1 h-px bg-slate-600/60"></div>
+        </div>
+    }
+}
+
+/// The text of a message, then whatever images and videos it links to.
+fn view_body(body: &str, collapse_code_after: usize) -> Html {
+    let document = markdown::parse(body);
+    html! {
+        <>
+            {document.view(collapse_code_after)}
+            {links::view_embeds(document.links())}
+        </>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str) -> ChatMessage {
+        serde_json::from_value(serde_json::json!({ "id": id, "from": "alice", "message": id })).unwrap()
+    }
+
+    fn timeline(ids: &[&str]) -> Timeline {
+        Timeline::cached(ids.iter().map(|id| message(id)).collect())
+    }
+
+    fn page(ids: &[&str]) -> Vec<ChatMessage> {
+        ids.iter().map(|id| message(id)).collect()
+    }
+
+    fn ids(timeline: &Timeline) -> Vec<&str> {
+        timeline.messages.iter().filter_map(|m| m.id.as_deref()).collect()
+    }
+
+    #[test]
+    fn older_page_goes_in_front() {
+        let mut timeline = timeline(&["C", "D"]);
+        timeline.merge_history(page(&["A", "B"]), true);
+        assert_eq!(ids(&timeline), ["A", "B", "C", "D"]);
+    }
+
+    #[test]
+    fn newer_page_goes_at_the_end() {
+        let mut timeline = timeline(&["A", "B"]);
+        timeline.merge_history(page(&["C", "D"]), false);
+        assert_eq!(ids(&timeline), ["A", "B", "C", "D"]);
+    }
+
+    #[test]
+    fn overlapping_page_is_merged_once() {
+        let mut timeline = timeline(&["B", "C"]);
+        timeline.merge_history(page(&["A", "B", "C", "D"]), false);
+        assert_eq!(ids(&timeline), ["A", "B", "C", "D"]);
+    }
+
+    #[test]
+    fn hole_in_the_middle_is_filled() {
+        let mut timeline = timeline(&["X", "L"]);
+        timeline.merge_history(page(&["X", "Y", "L"]), false);
+        assert_eq!(ids(&timeline), ["X", "Y", "L"]);
+    }
+
+    #[test]
+    fn several_holes_are_filled_in_order() {
+        let mut timeline = timeline(&["B", "E", "G"]);
+        timeline.merge_history(page(&["A", "B", "C", "D", "E", "F", "G", "H"]), true);
+        assert_eq!(ids(&timeline), ["A", "B", "C", "D", "E", "F", "G", "H"]);
+    }
+
+    fn reply(id: &str, parent: &str) -> ChatMessage {
+        ChatMessage {
+            reply_to: Some(parent.to_owned()),
+            ..message(id)
+        }
+    }
+
+    fn reply_count(timeline: &Timeline, id: &str) -> u32 {
+        timeline.find(id).unwrap().reply_count
+    }
+
+    #[test]
+    fn replies_in_a_page_are_not_counted_twice() {
+        let parent = ChatMessage {
+            reply_count: 1,
+            ..message("P")
+        };
+        let mut empty = Timeline::default();
+        empty.merge_history(vec![parent.clone(), reply("R", "P")], true);
+        assert_eq!(ids(&empty), ["P", "R"]);
+        assert_eq!(reply_count(&empty, "P"), 1);
+
+        let mut timeline = timeline(&["A"]);
+        timeline.merge_history(vec![message("A"), parent, reply("R", "P")], false);
+        assert_eq!(ids(&timeline), ["A", "P", "R"]);
+        assert_eq!(reply_count(&timeline, "P"), 1);
+    }
+
+    #[test]
+    fn replies_to_loaded_messages_are_counted() {
+        let mut timeline = timeline(&["P"]);
+        timeline.merge_history(page(&["P", "A"]), false);
+        timeline.merge_history(vec![message("A"), reply("R", "P")], false);
+        assert_eq!(ids(&timeline), ["P", "A", "R"]);
+        assert_eq!(reply_count(&timeline, "P"), 1);
+    }
+
+    #[test]
+    fn live_replies_are_counted() {
+        let mut timeline = timeline(&["P"]);
+        assert!(timeline.push(reply("R", "P")));
+        assert!(!timeline.push(reply("R", "P")));
+        assert_eq!(reply_count(&timeline, "P"), 1);
+    }
+
+    #[test]
+    fn live_messages_do_not_load_the_latest_page() {
+        let mut live = Timeline::default();
+        live.push(message("A"));
+        assert!(!live.latest_loaded);
+
+        let mut timeline = timeline(&["A"]);
+        timeline.push(message("B"));
+        timeline.merge_history(page(&["0"]), true);
+        assert!(!timeline.latest_loaded);
+        timeline.merge_history(page(&["A", "B"]), false);
+        assert!(timeline.latest_loaded);
+    }
+
+    #[test]
+    fn known_messages_are_updated() {
+        let mut timeline = timeline(&["A", "B"]);
+        let edited = ChatMessage {
+            message: "edited".to_owned(),
+            ..message("A")
+        };
+        timeline.merge_history(vec![edited, message("B")], true);
+        assert_eq!(ids(&timeline), ["A", "B"]);
+        assert_eq!(timeline.messages[0].message, "edited");
+    }
+}
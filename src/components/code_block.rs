@@ -0,0 +1,133 @@
+use gloo::timers::future::TimeoutFuture;
+use gloo::utils::window;
+use js_sys::{Function, Promise, Reflect};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use yew::prelude::*;
+
+use crate::components::highlight::highlight;
+
+/// Code blocks longer than this start collapsed, unless the user picked
+/// another limit.
+pub const DEFAULT_COLLAPSE_AFTER: usize = 15;
+/// Blocks get line numbers from this many lines on.
+const NUMBER_LINES_FROM: usize = 5;
+/// How long the copy button says it copied.
+const COPIED_FOR_MS: u32 = 2_000;
+
+#[derive(Properties, PartialEq)]
+pub struct Props {
+    pub language: Option<String>,
+    pub code: String,
+    /// Collapse blocks with more lines than this.
+    pub collapse_after: usize,
+}
+
+/// A fenced code block: highlighted, numbered when long, with a copy
+/// button, and collapsed when very long.
+#[function_component(CodeBlock)]
+pub fn code_block(props: &Props) -> Html {
+    let expanded = use_state(|| false);
+    let copied = use_state(|| false);
+
+    let lines = highlight(props.language.as_deref(), &props.code);
+    let total = lines.len();
+    let collapsible = total > props.collapse_after;
+    let shown = if collapsible && !*expanded { props.collapse_after } else { total };
+    let numbered = total >= NUMBER_LINES_FROM;
+
+    let copy = {
+        let code = props.code.clone();
+        let copied = copied.clone();
+        Callback::from(move |_| {
+            let code = code.clone();
+            let copied = copied.clone();
+            spawn_local(async move {
+                if copy_to_clipboard(&code).await {
+                    copied.set(true);
+                    TimeoutFuture::new(COPIED_FOR_MS).await;
+                    copied.set(false);
+                }
+            });
+        })
+    };
+    let toggle = {
+        let expanded = expanded.clone();
+        Callback::from(move |_| expanded.set(!*expanded))
+    };
+
+    html! {
+        <div class="my-2 bg-slate-900/80 border border-slate-600/50 rounded-lg overflow-hidden">
+            <div class="flex items-center justify-between px-3 py-1 bg-slate-800/80 border-b border-slate-600/50 text-xs font-mono text-slate-400">
+                <span>{props.language.clone().unwrap_or_else(|| "text".to_owned())}</span>
+                <button onclick={copy} class="hover:text-cyan-300 transition-colors">
+                    {if *copied { "COPIED" } else { "COPY" }}
+                </button>
+            </div>
+            <pre class="p-3 overflow-x-auto text-sm font-mono"><code>
+                {
+                    lines.iter().take(shown).enumerate().map(|(index, line)| html! {
+                        <div class="flex">
+                            {
+                                if numbered {
+                                    html! { <span class="flex-none w-8 pr-3 text-right text-slate-600 select-none">{index + 1}</span> }
+                                } else {
+                                    html! {}
+                                }
+                            }
+                            <span class="whitespace-pre">
+                                {
+                                    if line.is_empty() {
+                                        html! { {" "} }
+                                    } else {
+                                        line.iter().map(|(kind, text)| html! {
+                                            <span class={kind.class()}>{text}</span>
+                                        }).collect::<Html>()
+                                    }
+                                }
+                            </span>
+                        </div>
+                    }).collect::<Html>()
+                }
+            </code></pre>
+            {
+                if collapsible {
+                    let label = if *expanded {
+                        "COLLAPSE".to_owned()
+                    } else {
+                        format!("SHOW {} MORE LINES", total - shown)
+                    };
+                    html! {
+                        <button onclick={toggle} class="w-full py-1 bg-slate-800/60 border-t border-slate-600/50 text-xs font-mono text-cyan-400 hover:text-cyan-300">
+                            {label}
+                        </button>
+                    }
+                } else {
+                    html! {}
+                }
+            }
+        </div>
+    }
+}
+
+/// `navigator.clipboard.writeText`, which web-sys only offers behind its
+/// unstable APIs.
+async fn copy_to_clipboard(text: &str) -> bool {
+    let Ok(clipboard) = Reflect::get(&window().navigator(), &JsValue::from_str("clipboard")) else {
+        return false;
+    };
+    let write = Reflect::get(&clipboard, &JsValue::from_str("writeText"))
+        .ok()
+        .and_then(|write| write.dyn_into::<Function>().ok());
+    let Some(write) = write else {
+        log::warn!("No clipboard to copy to");
+        return false;
+    };
+    match write
+        .call1(&clipboard, &JsValue::from_str(text))
+        .map(|promise| promise.dyn_into::<Promise>())
+    {
+        Ok(Ok(promise)) => JsFuture::from(promise).await.is_ok(),
+        _ => false,
+    }
+}
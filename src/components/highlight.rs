@@ -0,0 +1,255 @@
+//! Syntax highlighting for code blocks, for what gets pasted most: Rust,
+//! SQL and shell.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    Plain,
+    Keyword,
+    String,
+    Comment,
+    Number,
+    /// `$HOME` and friends in shell.
+    Variable,
+}
+
+impl TokenKind {
+    pub fn class(self) -> &'static str {
+        match self {
+            TokenKind::Plain => "text-slate-200",
+            TokenKind::Keyword => "text-purple-300",
+            TokenKind::String => "text-green-300",
+            TokenKind::Comment => "text-slate-500 italic",
+            TokenKind::Number => "text-amber-300",
+            TokenKind::Variable => "text-cyan-300",
+        }
+    }
+}
+
+/// A line of highlighted code.
+pub type Line = Vec<(TokenKind, String)>;
+
+struct Syntax {
+    keywords: &'static [&'static str],
+    /// Keywords match in any case, as in SQL.
+    ignore_case: bool,
+    line_comments: &'static [&'static str],
+    /// Line comments only start a line or follow whitespace, as `#` in
+    /// shells, where `a#b` is a word.
+    comments_after_space: bool,
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    variables: bool,
+}
+
+const RUST: Syntax = Syntax {
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false", "fn",
+        "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self",
+        "Self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+    ],
+    ignore_case: false,
+    line_comments: &["//"],
+    comments_after_space: false,
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"'],
+    variables: false,
+};
+
+const SQL: Syntax = Syntax {
+    keywords: &[
+        "add", "alter", "and", "as", "asc", "begin", "between", "by", "case", "commit", "create", "delete", "desc",
+        "distinct", "drop", "else", "end", "exists", "from", "group", "having", "in", "index", "inner", "insert",
+        "into", "is", "join", "key", "left", "like", "limit", "not", "null", "offset", "on", "or", "order", "outer",
+        "primary", "references", "returning", "right", "rollback", "select", "set", "table", "then", "union",
+        "update", "values", "when", "where", "with",
+    ],
+    ignore_case: true,
+    line_comments: &["--"],
+    comments_after_space: false,
+    block_comment: Some(("/*", "*/")),
+    quotes: &['\'', '"'],
+    variables: false,
+};
+
+const SHELL: Syntax = Syntax {
+    keywords: &[
+        "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in", "local",
+        "return", "then", "until", "while",
+    ],
+    ignore_case: false,
+    line_comments: &["#"],
+    comments_after_space: true,
+    block_comment: None,
+    quotes: &['\'', '"'],
+    variables: true,
+};
+
+fn syntax(language: &str) -> Option<&'static Syntax> {
+    match language.to_ascii_lowercase().as_str() {
+        "rust" | "rs" => Some(&RUST),
+        "sql" | "psql" | "mysql" | "sqlite" | "postgres" => Some(&SQL),
+        "sh" | "bash" | "zsh" | "shell" | "console" => Some(&SHELL),
+        _ => None,
+    }
+}
+
+/// Splits `code` into lines of highlighted tokens. Languages we do not
+/// know come back as plain text.
+pub fn highlight(language: Option<&str>, code: &str) -> Vec<Line> {
+    let tokens = match language.and_then(syntax) {
+        Some(syntax) => tokenize(syntax, code),
+        None => vec![(TokenKind::Plain, code)],
+    };
+
+    let mut lines: Vec<Line> = vec![vec![]];
+    for (kind, text) in tokens {
+        for (index, part) in text.split('\n').enumerate() {
+            if index > 0 {
+                lines.push(vec![]);
+            }
+            if !part.is_empty() {
+                lines.last_mut().unwrap().push((kind, part.to_owned()));
+            }
+        }
+    }
+    lines
+}
+
+fn tokenize<'a>(syntax: &Syntax, code: &'a str) -> Vec<(TokenKind, &'a str)> {
+    let mut tokens: Vec<(TokenKind, &str)> = vec![];
+    let mut start = 0;
+
+    while let Some(c) = code[start..].chars().next() {
+        let rest = &code[start..];
+        let variable = if syntax.variables && c == '$' { variable_len(rest) } else { 0 };
+        let after_space = code[..start].chars().next_back().is_none_or(char::is_whitespace);
+        let (kind, len) = if variable > 1 {
+            (TokenKind::Variable, variable)
+        } else if let Some(len) = comment_len(syntax, rest, after_space) {
+            (TokenKind::Comment, len)
+        } else if syntax.quotes.contains(&c) {
+            (TokenKind::String, string_len(rest, c))
+        } else if c.is_ascii_digit() {
+            (TokenKind::Number, word_len(rest))
+        } else if c.is_alphabetic() || c == '_' {
+            let len = word_len(rest);
+            (keyword_kind(syntax, &rest[..len]), len)
+        } else {
+            (TokenKind::Plain, c.len_utf8())
+        };
+
+        match tokens.last_mut() {
+            // Tokens are contiguous, so a neighbour of the same kind just
+            // grows. Keeps plain runs from becoming a span per character.
+            Some((last, merged)) if *last == kind => *merged = &code[start - merged.len()..start + len],
+            _ => tokens.push((kind, &code[start..start + len])),
+        }
+        start += len;
+    }
+    tokens
+}
+
+fn keyword_kind(syntax: &Syntax, word: &str) -> TokenKind {
+    let keyword = if syntax.ignore_case {
+        syntax.keywords.iter().any(|k| k.eq_ignore_ascii_case(word))
+    } else {
+        syntax.keywords.contains(&word)
+    };
+    if keyword {
+        TokenKind::Keyword
+    } else {
+        TokenKind::Plain
+    }
+}
+
+fn word_len(text: &str) -> usize {
+    text.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+        .unwrap_or(text.len())
+}
+
+/// `after_space` tells whether `text` starts a line or follows whitespace.
+fn comment_len(syntax: &Syntax, text: &str, after_space: bool) -> Option<usize> {
+    let may_start = after_space || !syntax.comments_after_space;
+    if may_start && syntax.line_comments.iter().any(|prefix| text.starts_with(prefix)) {
+        return Some(text.find('\n').unwrap_or(text.len()));
+    }
+    let (open, close) = syntax.block_comment?;
+    let after = text.strip_prefix(open)?;
+    Some(match after.find(close) {
+        Some(end) => open.len() + end + close.len(),
+        None => text.len(),
+    })
+}
+
+/// Up to and including the closing `quote`. Unterminated strings end with
+/// the line, so one stray quote does not paint the rest of the block.
+fn string_len(text: &str, quote: char) -> usize {
+    let mut escaped = false;
+    for (index, c) in text.char_indices().skip(1) {
+        match c {
+            '\n' => return index,
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if c == quote => return index + c.len_utf8(),
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// `$NAME`, `${NAME}` or a special parameter like `$?`. Just the `$` when
+/// none follows.
+fn variable_len(text: &str) -> usize {
+    let after = &text[1..];
+    if after.starts_with('{') {
+        return after.find('}').map(|end| end + 2).unwrap_or(1);
+    }
+    match after.chars().next() {
+        Some(c) if c.is_alphabetic() || c == '_' => {
+            1 + after
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(after.len())
+        }
+        Some(c) if "?#@*$!0123456789".contains(c) => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comments(language: &str, code: &str) -> Vec<String> {
+        highlight(Some(language), code)
+            .into_iter()
+            .flatten()
+            .filter(|(kind, _)| *kind == TokenKind::Comment)
+            .map(|(_, text)| text)
+            .collect()
+    }
+
+    #[test]
+    fn shell_comments_follow_whitespace() {
+        assert_eq!(comments("sh", "# setup\necho hi # greet"), ["# setup", "# greet"]);
+        assert!(comments("sh", "echo a#b").is_empty());
+        assert!(comments("sh", "curl https://example.com/#top").is_empty());
+        assert!(comments("sh", "echo ${#items[@]}").is_empty());
+    }
+
+    #[test]
+    fn other_comments_start_anywhere() {
+        assert_eq!(comments("rust", "let x = 1;// one"), ["// one"]);
+        assert_eq!(comments("sql", "select 1--one"), ["--one"]);
+    }
+
+    #[test]
+    fn shell_variables() {
+        let variables: Vec<String> = highlight(Some("sh"), "echo $HOME ${PATH} $? $ done")
+            .into_iter()
+            .flatten()
+            .filter(|(kind, _)| *kind == TokenKind::Variable)
+            .map(|(_, text)| text)
+            .collect();
+        assert_eq!(variables, ["$HOME", "${PATH}", "$?"]);
+    }
+}
@@ -0,0 +1,215 @@
+//! Finding URLs in message text, and embedding the images and videos they
+//! point at.
+
+use yew::prelude::*;
+
+/// Links may only use these schemes. Everything else, `javascript:` and
+/// `data:` above all, stays plain text.
+const SAFE_SCHEMES: [&str; 3] = ["http://", "https://", "mailto:"];
+/// Schemes a bare URL in text can start with.
+const AUTOLINK_SCHEMES: [&str; 2] = ["http://", "https://"];
+/// Characters a URL in text cannot contain.
+const URL_TERMINATORS: [char; 5] = ['<', '>', '"', '`', '\''];
+/// Punctuation that ends a sentence rather than the URL before it.
+const TRAILING_PUNCTUATION: [char; 7] = ['.', ',', ':', ';', '!', '?', '*'];
+/// No message embeds more than this many images and videos.
+const MAX_EMBEDS: usize = 4;
+
+const IMAGE_EXTENSIONS: [&str; 7] = ["jpg", "jpeg", "png", "gif", "webp", "avif", "bmp"];
+const VIDEO_EXTENSIONS: [&str; 4] = ["mp4", "webm", "ogv", "mov"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Embed {
+    Image,
+    Video,
+}
+
+/// Length of the http(s) URL `text` starts with, if it does. Whether the
+/// URL starts at a word boundary is up to the caller. Trailing
+/// punctuation and unbalanced closing brackets are left out, so that
+/// "(see https://example.com)." links just the URL.
+pub fn url_len(text: &str) -> Option<usize> {
+    let scheme = AUTOLINK_SCHEMES
+        .iter()
+        .find(|scheme| text.get(..scheme.len()).is_some_and(|start| start.eq_ignore_ascii_case(scheme)))?;
+    let end = text
+        .find(|c: char| c.is_whitespace() || c.is_control() || URL_TERMINATORS.contains(&c))
+        .unwrap_or(text.len());
+    let mut url = &text[..end];
+
+    loop {
+        let trimmed = url.trim_end_matches(TRAILING_PUNCTUATION);
+        let trimmed = match trimmed.chars().last() {
+            Some(close @ (')' | ']')) if unbalanced(trimmed, close) => &trimmed[..trimmed.len() - 1],
+            _ => trimmed,
+        };
+        if trimmed.len() == url.len() {
+            break;
+        }
+        url = trimmed;
+    }
+
+    (url.len() > scheme.len()).then_some(url.len())
+}
+
+fn unbalanced(url: &str, close: char) -> bool {
+    let open = if close == ')' { '(' } else { '[' };
+    url.matches(close).count() > url.matches(open).count()
+}
+
+/// Whether `href` may go into a link.
+pub fn is_safe_url(href: &str) -> bool {
+    let lower = href.trim_start().to_ascii_lowercase();
+    !href.chars().any(|c| c.is_whitespace() || c.is_control())
+        && SAFE_SCHEMES.iter().any(|scheme| lower.starts_with(scheme))
+}
+
+/// Whether `url` is an http(s) URL, the only kind we load anything from.
+pub fn is_web_url(url: &str) -> bool {
+    let lower = url.to_ascii_lowercase();
+    is_safe_url(url) && AUTOLINK_SCHEMES.iter().any(|scheme| lower.starts_with(scheme))
+}
+
+/// Whether `url` points at an image or video, going by the extension of
+/// its path. The query and fragment do not matter.
+pub fn embed_kind(url: &str) -> Option<Embed> {
+    if !is_web_url(url) {
+        return None;
+    }
+    let lower = url.to_ascii_lowercase();
+    let path = lower.split(['?', '#']).next().unwrap_or_default();
+    let (_, host_and_path) = path.split_once("://")?;
+    let (_, path) = host_and_path.split_once('/')?;
+    let (_, extension) = path.rsplit('/').next()?.rsplit_once('.')?;
+    if IMAGE_EXTENSIONS.contains(&extension) {
+        Some(Embed::Image)
+    } else if VIDEO_EXTENSIONS.contains(&extension) {
+        Some(Embed::Video)
+    } else {
+        None
+    }
+}
+
+/// The images and videos among `urls`, below the text of a message.
+pub fn view_embeds<'a>(urls: impl IntoIterator<Item = &'a str>) -> Html {
+    let mut seen = vec![];
+    let embeds: Vec<Html> = urls
+        .into_iter()
+        .filter_map(|url| Some((url, embed_kind(url)?)))
+        .filter(|(url, _)| {
+            let new = !seen.contains(url);
+            seen.push(*url);
+            new
+        })
+        .take(MAX_EMBEDS)
+        .map(|(url, embed)| view_embed(url, embed))
+        .collect();
+    if embeds.is_empty() {
+        return html! {};
+    }
+
+    html! {
+        <div class="flex flex-wrap gap-3 mt-3">{embeds}</div>
+    }
+}
+
+fn view_embed(url: &str, embed: Embed) -> Html {
+    match embed {
+        Embed::Image => html! {
+            <a href={url.to_owned()} target="_blank" rel="noopener noreferrer">
+                <img class="max-w-sm max-h-80 rounded-xl border border-cyan-500/30 shadow-lg shadow-cyan-400/20"
+                    src={url.to_owned()}
+                    alt="Quantum data stream"
+                    loading="lazy"
+                    referrerpolicy="no-referrer"/>
+            </a>
+        },
+        Embed::Video => html! {
+            <video class="max-w-md max-h-80 rounded-xl border border-cyan-500/30 shadow-lg shadow-cyan-400/20"
+                src={url.to_owned()}
+                controls=true
+                preload="metadata"/>
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The URL at the start of `text`, as `url_len` measures it.
+    fn url(text: &str) -> Option<&str> {
+        url_len(text).map(|len| &text[..len])
+    }
+
+    #[test]
+    fn embeds_go_by_the_extension_of_the_path() {
+        assert_eq!(embed_kind("https://x/cat.png?w=200#f"), Some(Embed::Image));
+        assert_eq!(embed_kind("https://x/CAT.JPG"), Some(Embed::Image));
+        assert_eq!(embed_kind("http://x/clip.webm"), Some(Embed::Video));
+        assert_eq!(embed_kind("https://x/page?file=cat.png"), None);
+        assert_eq!(embed_kind("https://x/cat.png.html"), None);
+        assert_eq!(embed_kind("https://cat.png"), None);
+        assert_eq!(embed_kind("mailto:cat.png"), None);
+    }
+
+    #[test]
+    fn only_safe_schemes_are_links() {
+        for href in [
+            "javascript:alert(1)",
+            " JaVaScRiPt:alert(1)",
+            "data:text/html,<script>alert(1)</script>",
+            "data:image/png;base64,AAAA",
+            "vbscript:msgbox",
+            "https://example.com/a b",
+            "https://example.com/\u{0}",
+        ] {
+            assert!(!is_safe_url(href), "{:?}", href);
+            assert!(!is_web_url(href), "{:?}", href);
+            assert_eq!(embed_kind(href), None, "{:?}", href);
+        }
+        assert!(is_safe_url("HTTPS://example.com"));
+        assert!(is_safe_url("mailto:a@example.com"));
+        assert!(!is_web_url("mailto:a@example.com"));
+        assert!(is_web_url("http://example.com"));
+    }
+
+    #[test]
+    fn urls_are_found_mid_sentence() {
+        let text = "have a look at https://example.com/docs?page=2 before lunch";
+        let start = text.find("https").unwrap();
+        assert_eq!(url(&text[start..]), Some("https://example.com/docs?page=2"));
+        assert_eq!(url(text), None);
+        assert_eq!(url("https://"), None);
+        assert_eq!(url("ftp://example.com"), None);
+        assert_eq!(url("<https://example.com>"), None);
+        assert_eq!(url("https://example.com>"), Some("https://example.com"));
+    }
+
+    #[test]
+    fn trailing_punctuation_is_left_out() {
+        assert_eq!(url("https://example.com."), Some("https://example.com"));
+        assert_eq!(url("https://example.com/a,"), Some("https://example.com/a"));
+        assert_eq!(url("https://example.com/a)."), Some("https://example.com/a"));
+        assert_eq!(url("https://example.com/?!..."), Some("https://example.com/"));
+    }
+
+    #[test]
+    fn balanced_brackets_stay() {
+        assert_eq!(
+            url("https://en.wikipedia.org/wiki/Rust_(language))."),
+            Some("https://en.wikipedia.org/wiki/Rust_(language)")
+        );
+        assert_eq!(url("https://example.com/a[1]"), Some("https://example.com/a[1]"));
+        assert_eq!(url("https://example.com/a]"), Some("https://example.com/a"));
+    }
+
+    #[test]
+    fn multibyte_text_around_urls() {
+        let text = "日本 https://example.com/über/café. 🦀";
+        let start = text.find("https").unwrap();
+        assert_eq!(url(&text[start..]), Some("https://example.com/über/café"));
+        assert_eq!(url("https://例え.jp/パス, はい"), Some("https://例え.jp/パス"));
+        assert_eq!(url("https://example.com/🦀)"), Some("https://example.com/🦀"));
+    }
+}
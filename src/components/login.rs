@@ -1,16 +1,108 @@
+use wasm_bindgen_futures::spawn_local;
 use web_sys::HtmlInputElement;
 use yew::functional::*;
 use yew::prelude::*;
+use yew_agent::use_bridge;
 use yew_router::prelude::*;
 
+use crate::protocol::{ErrorCode, ServerFrame};
+use crate::services::auth::{self, AuthError};
+use crate::services::event_bus::{Event, EventBus, Request, Topic};
+use crate::services::session::Session;
+use crate::services::websocket::{ConnectionState, Registered, WebsocketService};
 use crate::Route;
 use crate::User;
 
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    SignIn,
+    SignUp,
+}
+
+/// Progress of the current login attempt.
+#[derive(Clone, Debug, PartialEq)]
+enum Registration {
+    Idle,
+    Pending,
+    /// The server knows us, with the users it listed at the time.
+    Accepted(Vec<String>),
+    Rejected(String),
+}
+
+/// A registration in flight, kept until the server answers.
+struct Attempt {
+    session: Session,
+    service: WebsocketService,
+}
+
 #[function_component(Login)]
 pub fn login() -> Html {
-    let username = use_state(|| String::new());
+    let username = use_state(String::new);
+    let password = use_state(String::new);
+    let server = use_state(String::new);
+    let mode = use_state(|| Mode::SignIn);
     let user = use_context::<User>().expect("No context found.");
+    let history = use_history().expect("Login rendered outside of a router");
     let is_focused = use_state(|| false);
+    let registration = use_state(|| Registration::Idle);
+    let attempt = use_mut_ref(|| None::<Attempt>);
+
+    let bus = {
+        let registration = registration.clone();
+        let username = (*username).clone();
+        use_bridge::<EventBus, _>(move |event| {
+            if *registration != Registration::Pending {
+                return;
+            }
+            match event {
+                // Legacy servers have no explicit confirmation, but list us
+                // among the users once they accepted the name.
+                Event::Server(ServerFrame::Users { users }) if users.contains(&username) => {
+                    registration.set(Registration::Accepted(users))
+                }
+                Event::Server(ServerFrame::Error { reason, code }) => {
+                    registration.set(Registration::Rejected(rejection_message(reason, code)))
+                }
+                // Rather than retrying forever, let the user check the server.
+                Event::Connection(ConnectionState::Reconnecting { .. }) => {
+                    registration.set(Registration::Rejected("Could not reach the server.".into()))
+                }
+                _ => {}
+            }
+        })
+    };
+
+    {
+        let bus = bus.clone();
+        let attempt = attempt.clone();
+        let user = user.clone();
+        use_effect_with_deps(
+            move |registration| {
+                match registration {
+                    Registration::Accepted(users) => {
+                        bus.send(Request::Unsubscribe(vec![Topic::Server, Topic::Connection]));
+                        if let Some(Attempt { session, service }) = attempt.borrow_mut().take() {
+                            session.save();
+                            *user.session.borrow_mut() = Some(session);
+                            *user.connection.borrow_mut() = Some(Registered {
+                                service,
+                                users: users.clone(),
+                            });
+                            history.push(Route::Chat);
+                        }
+                    }
+                    Registration::Rejected(_) => {
+                        bus.send(Request::Unsubscribe(vec![Topic::Server, Topic::Connection]));
+                        // Dropping the service closes the socket.
+                        attempt.borrow_mut().take();
+                    }
+                    Registration::Idle | Registration::Pending => {}
+                }
+                || ()
+            },
+            (*registration).clone(),
+        );
+    }
 
     let oninput = {
         let current_username = username.clone();
@@ -21,10 +113,79 @@ pub fn login() -> Html {
         })
     };
 
+    let onpasswordinput = {
+        let current_password = password.clone();
+
+        Callback::from(move |e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            current_password.set(input.value());
+        })
+    };
+
+    let onserverinput = {
+        let current_server = server.clone();
+
+        Callback::from(move |e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            current_server.set(input.value());
+        })
+    };
+
     let onclick = {
         let username = username.clone();
-        let user = user.clone();
-        Callback::from(move |_| *user.username.borrow_mut() = (*username).clone())
+        let password = password.clone();
+        let server = server.clone();
+        let mode = mode.clone();
+        let registration = registration.clone();
+        let attempt = attempt.clone();
+        Callback::from(move |_| {
+            let username = (*username).clone();
+            let password = (*password).clone();
+            let server = Some(server.trim().to_owned()).filter(|s| !s.is_empty());
+            let mode = *mode;
+            let bus = bus.clone();
+            let registration = registration.clone();
+            let attempt = attempt.clone();
+
+            registration.set(Registration::Pending);
+            spawn_local(async move {
+                let token = match mode {
+                    Mode::SignIn => auth::sign_in(server.as_deref(), &username, &password).await,
+                    Mode::SignUp => auth::sign_up(server.as_deref(), &username, &password).await,
+                };
+                let token = match token {
+                    Ok(token) => token,
+                    Err(AuthError::Rejected { reason, code }) => {
+                        registration.set(Registration::Rejected(rejection_message(reason, code)));
+                        return;
+                    }
+                    Err(e) => {
+                        registration.set(Registration::Rejected(e.to_string()));
+                        return;
+                    }
+                };
+
+                // Subscribe before connecting so the answer cannot slip past.
+                bus.send(Request::Subscribe(vec![Topic::Server, Topic::Connection]));
+                let service = WebsocketService::new(username.clone(), server.clone(), token.clone());
+                let session = Session::new(username, server, token);
+                *attempt.borrow_mut() = Some(Attempt { session, service });
+            });
+        })
+    };
+
+    let mode_tab = |tab: Mode, label: &'static str| {
+        let mode = mode.clone();
+        let selected = *mode == tab;
+        let onclick = Callback::from(move |_| mode.set(tab));
+        let classes = if selected {
+            "flex-1 py-2 text-xs font-mono tracking-widest text-cyan-300 border-b-2 border-cyan-400"
+        } else {
+            "flex-1 py-2 text-xs font-mono tracking-widest text-gray-500 border-b-2 border-transparent hover:text-gray-300"
+        };
+        html! {
+            <button {onclick} disabled={*registration == Registration::Pending} class={classes}>{label}</button>
+        }
     };
 
     let onfocus = {
@@ -90,7 +251,13 @@ pub fn login() -> Html {
         "text-xs", "font-mono", "bg-blue-900", "bg-opacity-50", "text-blue-200"
     );
 
-    let link_classes = classes!("block");
+    let pending = *registration == Registration::Pending;
+    let rejection = match &*registration {
+        Registration::Rejected(reason) => html! {
+            <p class="mt-2 ml-1 text-sm font-mono text-red-400">{reason}</p>
+        },
+        _ => html! {},
+    };
 
     html! {
         <div class={container_classes}>
@@ -112,7 +279,12 @@ pub fn login() -> Html {
                         <p class="text-gray-400 text-sm">{"SYSTEM ACCESS PROTOCOL"}</p>
                     </div>
                     
-                    <div class="mt-8 space-y-5">
+                    <div class="mt-6 flex">
+                        {mode_tab(Mode::SignIn, "SIGN IN")}
+                        {mode_tab(Mode::SignUp, "SIGN UP")}
+                    </div>
+
+                    <div class="mt-6 space-y-5">
                         <div class="relative">
                             <div class={input_glow_classes}></div>
                             <div class="relative">
@@ -126,25 +298,58 @@ pub fn login() -> Html {
                                     {oninput}
                                     {onfocus}
                                     {onblur}
-                                    class={input_classes}
+                                    disabled={pending}
+                                    class={input_classes.clone()}
                                     placeholder="Enter your username"
                                 />
                             </div>
                         </div>
+
+                        <div class="relative">
+                            <label for="password" class="block text-xs font-mono tracking-widest text-gray-400 uppercase mb-1 ml-1">
+                                {"Passphrase"}
+                            </label>
+                            <input
+                                id="password"
+                                type="password"
+                                required=true
+                                oninput={onpasswordinput}
+                                disabled={pending}
+                                class={input_classes.clone()}
+                                placeholder="Enter your password"
+                            />
+                            {rejection}
+                        </div>
+
+                        <div class="relative">
+                            <label for="server" class="block text-xs font-mono tracking-widest text-gray-400 uppercase mb-1 ml-1">
+                                {"Server (optional)"}
+                            </label>
+                            <input
+                                id="server"
+                                type="text"
+                                oninput={onserverinput}
+                                disabled={pending}
+                                class={input_classes}
+                                placeholder="Default server"
+                            />
+                        </div>
                         
                         <div class="relative">
-                            <Link<Route> to={Route::Chat} classes={link_classes}>
-                                <div class={button_glow_classes}></div>
-                                <button
-                                    {onclick}
-                                    disabled={username.len() < 1}
-                                    class={button_classes}
-                                >
-                                    <span class="absolute inset-0 flex items-center justify-center">
-                                        {"INITIALIZE SESSION"}
-                                    </span>
-                                </button>
-                            </Link<Route>>
+                            <div class={button_glow_classes}></div>
+                            <button
+                                {onclick}
+                                disabled={username.is_empty() || password.is_empty() || pending}
+                                class={button_classes}
+                            >
+                                <span class="absolute inset-0 flex items-center justify-center">
+                                    {match (pending, *mode) {
+                                        (true, _) => "AUTHENTICATING...",
+                                        (false, Mode::SignIn) => "INITIALIZE SESSION",
+                                        (false, Mode::SignUp) => "CREATE IDENTITY",
+                                    }}
+                                </span>
+                            </button>
                         </div>
                     </div>
                     
@@ -158,4 +363,17 @@ pub fn login() -> Html {
             </div>
         </div>
     }
-}
\ No newline at end of file
+}
+
+/// What to tell the user when the server refused to sign them in or to
+/// register them.
+fn rejection_message(reason: String, code: Option<ErrorCode>) -> String {
+    match code {
+        Some(ErrorCode::NameTaken) => "That name is already taken.".into(),
+        Some(ErrorCode::InvalidName) => "That name contains characters the server does not allow.".into(),
+        Some(ErrorCode::ServerFull) => "The server is full, try again later.".into(),
+        Some(ErrorCode::InvalidCredentials) => "Wrong username or password.".into(),
+        Some(ErrorCode::Unauthorized) => "The server did not accept the session, sign in again.".into(),
+        Some(ErrorCode::Other) | None => reason,
+    }
+}
@@ -0,0 +1,534 @@
+//! The markdown subset messages may use: **bold**, *italic*, ~~strike~~,
+//! `code`, fenced code blocks, lists, block quotes and `[links](url)`. Bare
+//! http(s) URLs become links too.
+//!
+//! Everything ends up as Yew text nodes and elements built here, raw HTML
+//! in a message is just text.
+
+use yew::prelude::*;
+
+use crate::components::code_block::CodeBlock;
+use crate::components::links;
+
+/// Quotes in quotes, and emphasis in emphasis, stop nesting this deep.
+const MAX_DEPTH: usize = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Block {
+    Paragraph(Vec<Inline>),
+    Code { language: Option<String>, text: String },
+    List { ordered: bool, items: Vec<Vec<Inline>> },
+    Quote(Vec<Block>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Inline {
+    Text(String),
+    Strong(Vec<Inline>),
+    Emphasis(Vec<Inline>),
+    Strike(Vec<Inline>),
+    Code(String),
+    Link { href: String, children: Vec<Inline> },
+    Break,
+}
+
+/// A parsed message body.
+pub struct Document {
+    blocks: Vec<Block>,
+}
+
+pub fn parse(text: &str) -> Document {
+    Document {
+        blocks: parse_blocks(text, 0),
+    }
+}
+
+impl Document {
+    /// Code blocks longer than `collapse_code_after` lines start collapsed.
+    pub fn view(&self, collapse_code_after: usize) -> Html {
+        self.blocks
+            .iter()
+            .map(|block| view_block(block, collapse_code_after))
+            .collect()
+    }
+
+    /// Where the links in the text go, in order. Code is not looked at.
+    pub fn links(&self) -> Vec<&str> {
+        let mut hrefs = vec![];
+        for block in &self.blocks {
+            block_links(block, &mut hrefs);
+        }
+        hrefs
+    }
+}
+
+fn block_links<'a>(block: &'a Block, hrefs: &mut Vec<&'a str>) {
+    match block {
+        Block::Paragraph(inlines) => inline_links(inlines, hrefs),
+        Block::List { items, .. } => items.iter().for_each(|item| inline_links(item, hrefs)),
+        Block::Quote(blocks) => blocks.iter().for_each(|block| block_links(block, hrefs)),
+        Block::Code { .. } => {}
+    }
+}
+
+fn inline_links<'a>(inlines: &'a [Inline], hrefs: &mut Vec<&'a str>) {
+    for inline in inlines {
+        match inline {
+            Inline::Link { href, .. } => hrefs.push(href),
+            Inline::Strong(children) | Inline::Emphasis(children) | Inline::Strike(children) => {
+                inline_links(children, hrefs)
+            }
+            Inline::Text(_) | Inline::Code(_) | Inline::Break => {}
+        }
+    }
+}
+
+fn parse_blocks(text: &str, depth: usize) -> Vec<Block> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut blocks = vec![];
+    let mut paragraph: Vec<&str> = vec![];
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim_start();
+
+        let block = if let Some(info) = trimmed.strip_prefix("```") {
+            // Runs to the closing fence, or to the end of the message.
+            let language = Some(info.trim()).filter(|info| !info.is_empty()).map(str::to_owned);
+            let start = i + 1;
+            let end = (start..lines.len())
+                .find(|&j| lines[j].trim_start().starts_with("```"))
+                .unwrap_or(lines.len());
+            i = end + 1;
+            Some(Block::Code {
+                language,
+                text: lines[start..end].join("\n"),
+            })
+        } else if trimmed.starts_with('>') && depth < MAX_DEPTH {
+            let mut quoted = vec![];
+            while let Some(rest) = lines.get(i).and_then(|line| line.trim_start().strip_prefix('>')) {
+                quoted.push(rest.strip_prefix(' ').unwrap_or(rest));
+                i += 1;
+            }
+            Some(Block::Quote(parse_blocks(&quoted.join("\n"), depth + 1)))
+        } else if let Some((ordered, _)) = list_item(trimmed) {
+            let mut items = vec![];
+            while let Some((same, item)) = lines.get(i).and_then(|line| list_item(line.trim_start())) {
+                if same != ordered {
+                    break;
+                }
+                items.push(parse_inlines(item, depth, true));
+                i += 1;
+            }
+            Some(Block::List { ordered, items })
+        } else if trimmed.is_empty() {
+            i += 1;
+            None
+        } else {
+            paragraph.push(line);
+            i += 1;
+            continue;
+        };
+
+        if !paragraph.is_empty() {
+            blocks.push(Block::Paragraph(parse_inlines(&paragraph.join("\n"), depth, true)));
+            paragraph.clear();
+        }
+        blocks.extend(block);
+    }
+    if !paragraph.is_empty() {
+        blocks.push(Block::Paragraph(parse_inlines(&paragraph.join("\n"), depth, true)));
+    }
+    blocks
+}
+
+/// Whether `line` starts a list item, ordered or not, and its text.
+fn list_item(line: &str) -> Option<(bool, &str)> {
+    for bullet in ["- ", "* ", "+ "] {
+        if let Some(item) = line.strip_prefix(bullet) {
+            return Some((false, item));
+        }
+    }
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 || digits > 9 {
+        return None;
+    }
+    let rest = &line[digits..];
+    rest.strip_prefix(". ")
+        .or_else(|| rest.strip_prefix(") "))
+        .map(|item| (true, item))
+}
+
+/// `autolink` turns bare URLs into links, which link labels must not.
+fn parse_inlines(text: &str, depth: usize, autolink: bool) -> Vec<Inline> {
+    let mut inlines = vec![];
+    let mut plain = String::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        let parsed = match c {
+            '\\' => rest[1..].chars().next().filter(char::is_ascii_punctuation).map(|escaped| {
+                plain.push(escaped);
+                (None, 1 + escaped.len_utf8())
+            }),
+            '`' => rest[1..].find('`').map(|end| (Some(Inline::Code(rest[1..=end].to_owned())), end + 2)),
+            '\n' => Some((Some(Inline::Break), 1)),
+            '[' => link(rest, depth),
+            // Taken whole, so that underscores in a URL are not emphasis.
+            'h' | 'H' if autolink && !plain.ends_with(|c: char| c.is_alphanumeric()) => {
+                links::url_len(rest).map(|len| {
+                    let url = &rest[..len];
+                    let link = Inline::Link {
+                        href: url.to_owned(),
+                        children: vec![Inline::Text(url.to_owned())],
+                    };
+                    (Some(link), len)
+                })
+            }
+            '*' if rest.starts_with("**") => delimited(rest, "**", depth, autolink, Inline::Strong),
+            '~' if rest.starts_with("~~") => delimited(rest, "~~", depth, autolink, Inline::Strike),
+            '*' => delimited(rest, "*", depth, autolink, Inline::Emphasis),
+            // Not inside words, so snake_case stays as it is.
+            '_' if !plain.ends_with(|c: char| c.is_alphanumeric()) => {
+                delimited(rest, "_", depth, autolink, Inline::Emphasis)
+            }
+            _ => None,
+        };
+
+        match parsed {
+            Some((inline, consumed)) => {
+                if let Some(inline) = inline {
+                    if !plain.is_empty() {
+                        inlines.push(Inline::Text(std::mem::take(&mut plain)));
+                    }
+                    inlines.push(inline);
+                }
+                rest = &rest[consumed..];
+            }
+            None => {
+                plain.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    if !plain.is_empty() {
+        inlines.push(Inline::Text(plain));
+    }
+    inlines
+}
+
+/// Text between a pair of `marker`s at the start of `text`, and how much of
+/// `text` that took. The content may not start or end with a space.
+fn delimited(
+    text: &str,
+    marker: &str,
+    depth: usize,
+    autolink: bool,
+    wrap: fn(Vec<Inline>) -> Inline,
+) -> Option<(Option<Inline>, usize)> {
+    if depth >= MAX_DEPTH {
+        return None;
+    }
+    let inner = &text[marker.len()..];
+    let end = find_closing(inner, marker)?;
+    let content = &inner[..end];
+    if content.is_empty() || content.starts_with(' ') || content.ends_with(' ') {
+        return None;
+    }
+    Some((Some(wrap(parse_inlines(content, depth + 1, autolink))), marker.len() * 2 + end))
+}
+
+/// Where the `marker` closing the span at the start of `text` is. A single
+/// `*` is not closed by half of a `**`.
+fn find_closing(text: &str, marker: &str) -> Option<usize> {
+    let mut from = 0;
+    while let Some(offset) = text[from..].find(marker) {
+        let at = from + offset;
+        let doubled = marker.len() == 1 && text[at + 1..].starts_with(marker);
+        if !doubled {
+            return Some(at);
+        }
+        from = at + 2;
+    }
+    None
+}
+
+/// `[text](href)` at the start of `text`. Links to anything but the safe
+/// schemes stay plain text.
+fn link(text: &str, depth: usize) -> Option<(Option<Inline>, usize)> {
+    if depth >= MAX_DEPTH {
+        return None;
+    }
+    let label_end = text.find("](")?;
+    let label = &text[1..label_end];
+    if label.contains('[') {
+        return None;
+    }
+    let target = &text[label_end + 2..];
+    let href_end = target.find(')')?;
+    let href = target[..href_end].trim();
+    if !links::is_safe_url(href) {
+        return None;
+    }
+    Some((
+        Some(Inline::Link {
+            href: href.to_owned(),
+            children: parse_inlines(label, depth + 1, false),
+        }),
+        label_end + 2 + href_end + 1,
+    ))
+}
+
+fn view_block(block: &Block, collapse_code_after: usize) -> Html {
+    match block {
+        Block::Paragraph(inlines) => html! { <p>{view_inlines(inlines)}</p> },
+        Block::Code { language, text } => html! {
+            <CodeBlock language={language.clone()} code={text.clone()} collapse_after={collapse_code_after}/>
+        },
+        Block::List { ordered: true, items } => html! {
+            <ol class="my-1 pl-6 list-decimal">{items.iter().map(|item| html! { <li>{view_inlines(item)}</li> }).collect::<Html>()}</ol>
+        },
+        Block::List { ordered: false, items } => html! {
+            <ul class="my-1 pl-6 list-disc">{items.iter().map(|item| html! { <li>{view_inlines(item)}</li> }).collect::<Html>()}</ul>
+        },
+        Block::Quote(blocks) => html! {
+            <blockquote class="my-1 pl-3 border-l-2 border-slate-500 text-slate-300">
+                {blocks.iter().map(|block| view_block(block, collapse_code_after)).collect::<Html>()}
+            </blockquote>
+        },
+    }
+}
+
+fn view_inlines(inlines: &[Inline]) -> Html {
+    inlines.iter().map(view_inline).collect()
+}
+
+fn view_inline(inline: &Inline) -> Html {
+    match inline {
+        Inline::Text(text) => html! { {text} },
+        Inline::Strong(children) => html! { <strong>{view_inlines(children)}</strong> },
+        Inline::Emphasis(children) => html! { <em>{view_inlines(children)}</em> },
+        Inline::Strike(children) => html! { <del>{view_inlines(children)}</del> },
+        Inline::Code(code) => html! {
+            <code class="px-1 py-0.5 bg-slate-900/80 rounded text-sm font-mono text-cyan-200">{code}</code>
+        },
+        Inline::Link { href, children } => html! {
+            <a href={href.clone()} target="_blank" rel="noopener noreferrer" class="text-cyan-300 underline hover:text-cyan-200">
+                {view_inlines(children)}
+            </a>
+        },
+        Inline::Break => html! { <br/> },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Inline {
+        Inline::Text(text.to_owned())
+    }
+
+    fn link(href: &str) -> Inline {
+        Inline::Link {
+            href: href.to_owned(),
+            children: vec![text(href)],
+        }
+    }
+
+    fn inlines(source: &str) -> Vec<Inline> {
+        match parse(source).blocks.as_slice() {
+            [Block::Paragraph(inlines)] => inlines.clone(),
+            blocks => panic!("{:?} is not a single paragraph: {:?}", source, blocks),
+        }
+    }
+
+    fn assert_plain(source: &str) {
+        assert_eq!(inlines(source), [text(source)], "{:?}", source);
+    }
+
+    fn quote_depth(blocks: &[Block]) -> usize {
+        blocks
+            .iter()
+            .map(|block| match block {
+                Block::Quote(blocks) => 1 + quote_depth(blocks),
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn inline_depth(inlines: &[Inline]) -> usize {
+        inlines
+            .iter()
+            .map(|inline| match inline {
+                Inline::Strong(children) | Inline::Emphasis(children) | Inline::Strike(children) => {
+                    1 + inline_depth(children)
+                }
+                Inline::Link { children, .. } => 1 + inline_depth(children),
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn unsafe_links_stay_text() {
+        for source in [
+            "[x](javascript:alert(1))",
+            "[x]( JaVaScRiPt:alert(1))",
+            "[x](data:text/html,<script>alert(1)</script>)",
+            "[x](vbscript:msgbox)",
+            "[x](&#106;avascript:alert(1))",
+            "[x](java\tscript:alert(1))",
+            "[x](//evil.example.com)",
+        ] {
+            let document = parse(source);
+            assert!(document.links().is_empty(), "{:?} linked {:?}", source, document.links());
+            assert_plain(source);
+        }
+    }
+
+    #[test]
+    fn safe_links_are_links() {
+        assert_eq!(
+            inlines("[docs](https://example.com/docs)"),
+            [Inline::Link {
+                href: "https://example.com/docs".to_owned(),
+                children: vec![text("docs")],
+            }]
+        );
+        assert_eq!(parse("[mail]( mailto:a@example.com )").links(), ["mailto:a@example.com"]);
+    }
+
+    #[test]
+    fn raw_html_stays_text() {
+        assert_plain("<script>alert(1)</script>");
+        assert_plain("<img src=x onerror=alert(1)>");
+        assert_plain("<a href=\"javascript:alert(1)\">x</a>");
+    }
+
+    #[test]
+    fn quotes_stop_nesting() {
+        let source = format!("{} deep", ">".repeat(10_000));
+        let blocks = parse(&source).blocks;
+        assert_eq!(quote_depth(&blocks), MAX_DEPTH);
+    }
+
+    #[test]
+    fn emphasis_stops_nesting() {
+        for marker in ["*", "**", "~~", "_"] {
+            let source = format!("{}x{}", marker.repeat(1_000), marker.repeat(1_000));
+            let depth = inline_depth(&inlines(&source));
+            assert!(depth <= MAX_DEPTH, "{} nested {} deep", marker, depth);
+        }
+        let source = "*a **b *c **d *e **f *g **h *i **j** i* h** g* f** e* d** c* b** a*";
+        assert!(inline_depth(&inlines(source)) <= MAX_DEPTH);
+    }
+
+    #[test]
+    fn links_stop_nesting() {
+        let source = format!("{}x{}", "*[".repeat(100), "](https://example.com)*".repeat(100));
+        assert!(inline_depth(&inlines(&source)) <= MAX_DEPTH);
+    }
+
+    #[test]
+    fn unclosed_markers_stay_text() {
+        assert_plain("**bold");
+        assert_plain("*italic");
+        assert_plain("~~strike");
+        assert_plain("`code");
+        assert_plain("[label");
+        // Only the bare URL is a link.
+        assert_eq!(
+            inlines("[label](https://example.com"),
+            [text("[label]("), link("https://example.com")]
+        );
+    }
+
+    #[test]
+    fn unclosed_fence_runs_to_the_end() {
+        assert_eq!(
+            parse("```rust\nfn main() {}\n\nmore").blocks,
+            [Block::Code {
+                language: Some("rust".to_owned()),
+                text: "fn main() {}\n\nmore".to_owned(),
+            }]
+        );
+        assert_eq!(
+            parse("```").blocks,
+            [Block::Code {
+                language: None,
+                text: String::new(),
+            }]
+        );
+    }
+
+    #[test]
+    fn multibyte_characters_next_to_markers() {
+        assert_eq!(inlines("\\é"), [text("\\é")]);
+        assert_eq!(inlines("\\*é"), [text("*é")]);
+        assert_eq!(inlines("`é`"), [Inline::Code("é".to_owned())]);
+        assert_eq!(inlines("*é*"), [Inline::Emphasis(vec![text("é")])]);
+        assert_eq!(
+            inlines("é**🦀**é"),
+            [text("é"), Inline::Strong(vec![text("🦀")]), text("é")]
+        );
+        assert_eq!(
+            inlines("[日本](https://example.com)"),
+            [Inline::Link {
+                href: "https://example.com".to_owned(),
+                children: vec![text("日本")],
+            }]
+        );
+        // No emphasis inside words, whatever the script.
+        assert_plain("日本_語_");
+    }
+
+    #[test]
+    fn no_marker_next_to_a_multibyte_character_panics() {
+        let markers = [
+            "\\", "`", "[", "](", ")", "*", "**", "_", "~~", "h", "http://", "https://x", "\n", "> ", "```", "- ", "1. ",
+        ];
+        let characters = ["é", "日", "🦀", "\u{200b}", "e\u{301}"];
+        for marker in markers {
+            for character in characters {
+                for source in [
+                    format!("{}{}", marker, character),
+                    format!("{}{}", character, marker),
+                    format!("{}{}{}", marker, character, marker),
+                    format!("{}{}{}", character, marker, character),
+                ] {
+                    parse(&source);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn autolinks_leave_out_trailing_punctuation() {
+        assert_eq!(
+            inlines("see https://example.com/path."),
+            [text("see "), link("https://example.com/path"), text(".")]
+        );
+        assert_eq!(
+            inlines("(see https://example.com)!"),
+            [text("(see "), link("https://example.com"), text(")!")]
+        );
+        assert_eq!(
+            inlines("https://en.wikipedia.org/wiki/Rust_(language), neat"),
+            [link("https://en.wikipedia.org/wiki/Rust_(language)"), text(", neat")]
+        );
+        assert_eq!(
+            inlines("**https://example.com**"),
+            [Inline::Strong(vec![link("https://example.com")])]
+        );
+    }
+
+    #[test]
+    fn autolinks_need_a_word_boundary() {
+        assert_plain("xhttps://example.com");
+        assert_plain("https://");
+    }
+}
@@ -1,2 +1,12 @@
+pub mod attachments;
 pub mod chat;
+pub mod code_block;
+pub mod highlight;
+pub mod links;
 pub mod login;
+pub mod markdown;
+pub mod previews;
+pub mod reactions;
+pub mod roster;
+pub mod time;
+pub mod typing;
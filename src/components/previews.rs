@@ -0,0 +1,91 @@
+//! Cards for the links in a message: title, description and thumbnail, as
+//! unfurled by the server.
+
+use yew::prelude::*;
+
+use crate::components::links;
+use crate::protocol::LinkPreview;
+
+/// No message shows more cards than this.
+const MAX_CARDS: usize = 3;
+/// Descriptions are cut after this many characters.
+const DESCRIPTION_LENGTH: usize = 200;
+
+/// A card per preview, below the text of a message. With `on_dismiss`,
+/// for the sender, each card gets a button that calls it with the URL.
+pub fn view_cards(previews: &[LinkPreview], on_dismiss: Option<&Callback<String>>) -> Html {
+    let cards: Vec<Html> = previews
+        .iter()
+        .filter(|preview| links::is_web_url(&preview.url))
+        .take(MAX_CARDS)
+        .map(|preview| view_card(preview, on_dismiss))
+        .collect();
+    if cards.is_empty() {
+        return html! {};
+    }
+
+    html! {
+        <div class="flex flex-col gap-2 mt-3">{cards}</div>
+    }
+}
+
+fn view_card(preview: &LinkPreview, on_dismiss: Option<&Callback<String>>) -> Html {
+    let title = preview.title.clone().unwrap_or_else(|| host(&preview.url).to_owned());
+    let thumbnail = match preview.image.as_deref().filter(|image| links::is_web_url(image)) {
+        Some(image) => html! {
+            <img class="flex-none w-20 h-20 object-cover rounded-lg border border-slate-600/50"
+                src={image.to_owned()}
+                alt=""
+                loading="lazy"
+                referrerpolicy="no-referrer"/>
+        },
+        None => html! {},
+    };
+    let dismiss = match on_dismiss {
+        Some(on_dismiss) => {
+            let url = preview.url.clone();
+            let onclick = on_dismiss.reform(move |_| url.clone());
+            html! {
+                <button {onclick} title="Remove preview" class="absolute top-1 right-2 text-slate-500 hover:text-red-300 transition-colors">
+                    {"×"}
+                </button>
+            }
+        }
+        None => html! {},
+    };
+
+    html! {
+        <div class="relative max-w-md border-l-2 border-cyan-400/60 bg-slate-900/50 rounded-r-lg">
+            <a href={preview.url.clone()} target="_blank" rel="noopener noreferrer" class="flex items-start gap-3 p-3 pr-6 hover:bg-slate-900/80 transition-colors">
+                <div class="flex-1 min-w-0">
+                    <div class="text-xs text-slate-500 font-mono truncate">{host(&preview.url)}</div>
+                    <div class="text-sm font-semibold text-cyan-300 truncate">{title}</div>
+                    {
+                        match &preview.description {
+                            Some(description) => html! {
+                                <p class="mt-1 text-xs text-slate-300">{shorten(description)}</p>
+                            },
+                            None => html! {},
+                        }
+                    }
+                </div>
+                {thumbnail}
+            </a>
+            {dismiss}
+        </div>
+    }
+}
+
+/// `example.com` out of `https://example.com/some/page`.
+pub fn host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    rest.split(['/', '?', '#']).next().unwrap_or(rest)
+}
+
+fn shorten(text: &str) -> String {
+    if text.chars().count() > DESCRIPTION_LENGTH {
+        format!("{}…", text.chars().take(DESCRIPTION_LENGTH).collect::<String>())
+    } else {
+        text.to_owned()
+    }
+}
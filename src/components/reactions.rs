@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+
+use yew::prelude::*;
+
+/// What the picker offers.
+const PALETTE: [&str; 8] = ["👍", "❤️", "😂", "🎉", "😮", "😢", "🔥", "👀"];
+
+/// One chip per emoji under a message, with how many reacted with it.
+/// Clicking a chip calls `on_toggle` with its emoji, to add or take back
+/// `me`'s reaction.
+pub fn view_chips(reactions: &BTreeMap<String, Vec<String>>, me: &str, on_toggle: &Callback<String>) -> Html {
+    if reactions.is_empty() {
+        return html! {};
+    }
+
+    html! {
+        <div class="flex flex-wrap gap-1 mt-2">
+            {
+                reactions.iter().map(|(emoji, users)| {
+                    let mine = users.iter().any(|user| user == me);
+                    let onclick = {
+                        let emoji = emoji.clone();
+                        on_toggle.reform(move |_| emoji.clone())
+                    };
+                    let classes = classes!(
+                        "flex", "items-center", "space-x-1", "px-2", "py-0.5", "rounded-full", "border",
+                        "text-xs", "transition-colors",
+                        if mine {
+                            "bg-cyan-500/20 border-cyan-400/60 text-cyan-200"
+                        } else {
+                            "bg-slate-700/40 border-slate-600/60 text-slate-300 hover:border-cyan-400/40"
+                        }
+                    );
+                    html! {
+                        <button {onclick} class={classes} title={tooltip(emoji, users, me)}>
+                            <span>{emoji}</span>
+                            <span class="font-mono">{users.len()}</span>
+                        </button>
+                    }
+                }).collect::<Html>()
+            }
+        </div>
+    }
+}
+
+/// A row of emoji to react with.
+pub fn view_picker(on_pick: &Callback<String>) -> Html {
+    html! {
+        <div class="flex mt-2 w-max bg-slate-800 border border-cyan-500/30 rounded-xl shadow-lg overflow-hidden">
+            {
+                PALETTE.iter().map(|&emoji| {
+                    let onclick = on_pick.reform(move |_| emoji.to_owned());
+                    html! {
+                        <button {onclick} class="px-2 py-1 text-lg hover:bg-cyan-400/10 transition-colors">{emoji}</button>
+                    }
+                }).collect::<Html>()
+            }
+        </div>
+    }
+}
+
+/// "You, alice and bob reacted with 👍"
+fn tooltip(emoji: &str, users: &[String], me: &str) -> String {
+    let names: Vec<&str> = users
+        .iter()
+        .map(|user| if user == me { "You" } else { user.as_str() })
+        .collect();
+    let who = match names.as_slice() {
+        [] => String::new(),
+        [one] => (*one).to_owned(),
+        [rest @ .., last] => format!("{} and {}", rest.join(", "), last),
+    };
+    format!("{} reacted with {}", who, emoji)
+}
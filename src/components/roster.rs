@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+use crate::components::time;
+use crate::protocol::Presence;
+use crate::Route;
+
+#[derive(Clone, Debug)]
+pub struct UserProfile {
+    pub name: String,
+    pub avatar: String,
+    pub presence: Presence,
+    /// `Date.now()` of when an offline user was last around, if known.
+    pub last_seen: Option<f64>,
+}
+
+impl UserProfile {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            avatar: format!("https://avatars.dicebear.com/api/adventurer-neutral/{}.svg", name),
+            presence: Presence::Offline,
+            last_seen: None,
+        }
+    }
+}
+
+/// Everyone we have heard of, with how available they are.
+#[derive(Default)]
+pub struct Roster {
+    users: BTreeMap<String, UserProfile>,
+}
+
+impl Roster {
+    /// Applies a `users` frame, which lists who is connected right now.
+    /// Whoever dropped off is marked offline as of now.
+    pub fn set_connected(&mut self, names: &[String]) {
+        let now = js_sys::Date::now();
+        for user in self.users.values_mut() {
+            if !names.contains(&user.name) && user.presence != Presence::Offline {
+                user.presence = Presence::Offline;
+                user.last_seen = Some(now);
+            }
+        }
+        for name in names {
+            let user = self
+                .users
+                .entry(name.clone())
+                .or_insert_with(|| UserProfile::new(name));
+            // Keep away or busy, which `users` knows nothing about.
+            if user.presence == Presence::Offline {
+                user.presence = Presence::Online;
+            }
+        }
+    }
+
+    /// Applies a `presence` frame.
+    pub fn update(&mut self, name: &str, presence: Presence, last_seen: Option<&str>) {
+        let user = self
+            .users
+            .entry(name.to_owned())
+            .or_insert_with(|| UserProfile::new(name));
+        user.presence = presence;
+        if presence == Presence::Offline {
+            user.last_seen = last_seen
+                .and_then(time::parse)
+                .or_else(|| Some(js_sys::Date::now()));
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&UserProfile> {
+        self.users.get(name)
+    }
+
+    /// How many users are connected, whatever their status.
+    pub fn connected(&self) -> usize {
+        self.users
+            .values()
+            .filter(|user| user.presence != Presence::Offline)
+            .count()
+    }
+
+    /// Users grouped by presence, most available first. Each group is
+    /// sorted by name, offline users by who was seen last.
+    fn grouped(&self) -> Vec<(Presence, Vec<&UserProfile>)> {
+        let mut groups: BTreeMap<Presence, Vec<&UserProfile>> = BTreeMap::new();
+        for user in self.users.values() {
+            groups.entry(user.presence).or_default().push(user);
+        }
+        for (presence, users) in groups.iter_mut() {
+            if *presence == Presence::Offline {
+                users.sort_by(|a, b| b.last_seen.unwrap_or(0.0).total_cmp(&a.last_seen.unwrap_or(0.0)));
+            } else {
+                users.sort_by_key(|user| user.name.to_lowercase());
+            }
+        }
+        groups.into_iter().collect()
+    }
+
+    /// The sidebar list of users. Clicking anyone but `me` opens a direct
+    /// conversation.
+    pub fn view(&self, me: &str) -> Html {
+        self.grouped()
+            .into_iter()
+            .map(|(presence, users)| {
+                html! {
+                    <>
+                        <div class="pt-2 text-xs font-mono tracking-widest text-cyan-400/70">
+                            {format!("{} — {}", group_label(presence), users.len())}
+                        </div>
+                        { users.into_iter().map(|user| view_user(user, me)).collect::<Html>() }
+                    </>
+                }
+            })
+            .collect()
+    }
+}
+
+fn view_user(user: &UserProfile, me: &str) -> Html {
+    let (dot, text) = presence_colors(user.presence);
+    let status = match (user.presence, user.last_seen) {
+        (Presence::Offline, Some(last_seen)) => format!("○ SEEN {}", time::relative(last_seen).to_uppercase()),
+        (presence, _) => status_label(presence).to_owned(),
+    };
+    let dimmed = if user.presence == Presence::Offline { "opacity-60" } else { "" };
+
+    let card = html! {
+        <div class={classes!("group", "p-4", "bg-gradient-to-r", "from-slate-700/50", "to-slate-800/50", "border", "border-cyan-500/20", "rounded-xl", "hover:border-cyan-400/50", "hover:shadow-lg", "hover:shadow-cyan-400/10", "transition-all", "duration-300", "backdrop-blur-sm", "hover:scale-[1.02]", dimmed)}>
+            <div class="flex items-center space-x-3">
+                <div class="relative">
+                    <div class="w-12 h-12 rounded-full bg-gradient-to-r from-cyan-400 to-blue-500 p-0.5">
+                        <img class="w-full h-full rounded-full border-2 border-slate-800"
+                             src={user.avatar.clone()}
+                             alt={format!("{}'s neural avatar", user.name)}/>
+                    </div>
+                    <div class={classes!("absolute", "-bottom-1", "-right-1", "w-4", "h-4", "border-2", "border-slate-800", "rounded-full", dot)}></div>
+                </div>
+                <div class="flex-1 min-w-0">
+                    <div class="text-sm font-semibold text-cyan-100 truncate group-hover:text-cyan-300 transition-colors">
+                        {&user.name}
+                    </div>
+                    <div class={classes!("text-xs", "font-medium", "tracking-wide", text)}>
+                        {status}
+                    </div>
+                </div>
+                <div class="w-2 h-8 bg-gradient-to-t from-cyan-500/20 to-cyan-400/40 rounded-full opacity-60 group-hover:opacity-100 transition-opacity"></div>
+            </div>
+        </div>
+    };
+
+    if user.name == me {
+        card
+    } else {
+        html! {
+            <Link<Route> to={Route::Dm { username: user.name.clone() }} classes={classes!("block")}>
+                {card}
+            </Link<Route>>
+        }
+    }
+}
+
+fn group_label(presence: Presence) -> &'static str {
+    match presence {
+        Presence::Online => "ONLINE",
+        Presence::Away => "AWAY",
+        Presence::DoNotDisturb => "DO NOT DISTURB",
+        Presence::Offline => "OFFLINE",
+    }
+}
+
+pub fn status_label(presence: Presence) -> &'static str {
+    match presence {
+        Presence::Online => "◉ ONLINE",
+        Presence::Away => "◐ AWAY",
+        Presence::DoNotDisturb => "⊘ DO NOT DISTURB",
+        Presence::Offline => "○ OFFLINE",
+    }
+}
+
+/// Classes for the status dot and the status text.
+fn presence_colors(presence: Presence) -> (&'static str, &'static str) {
+    match presence {
+        Presence::Online => ("bg-green-400 shadow-lg shadow-green-400/50 animate-pulse", "text-green-400"),
+        Presence::Away => ("bg-amber-400", "text-amber-400"),
+        Presence::DoNotDisturb => ("bg-red-400", "text-red-400"),
+        Presence::Offline => ("bg-slate-500", "text-slate-400"),
+    }
+}
@@ -0,0 +1,124 @@
+//! Formatting of message times, in the browser's locale.
+
+use js_sys::{Array, Date, Function, Intl, Object, Reflect};
+use wasm_bindgen::{JsCast, JsValue};
+
+const MINUTE_MS: f64 = 60_000.0;
+const HOUR_MS: f64 = 60.0 * MINUTE_MS;
+const DAY_MS: f64 = 24.0 * HOUR_MS;
+
+/// Milliseconds since the epoch for an ISO 8601 timestamp from the server.
+pub fn parse(timestamp: &str) -> Option<f64> {
+    let ms = Date::parse(timestamp);
+    (!ms.is_nan()).then_some(ms)
+}
+
+/// The current time as an ISO 8601 timestamp, like the server sends them.
+pub fn now_iso() -> String {
+    Date::new_0().to_iso_string().into()
+}
+
+/// "now", "5 minutes ago", "3 hours ago" or "yesterday", as the locale
+/// says them.
+pub fn relative(ms: f64) -> String {
+    let elapsed = (Date::now() - ms).max(0.0);
+    let (value, unit) = if elapsed < MINUTE_MS {
+        (0.0, "second")
+    } else if elapsed < HOUR_MS {
+        (-(elapsed / MINUTE_MS).floor(), "minute")
+    } else if elapsed < DAY_MS {
+        (-(elapsed / HOUR_MS).floor(), "hour")
+    } else {
+        (-(elapsed / DAY_MS).floor(), "day")
+    };
+    format_relative(value, unit).unwrap_or_else(|| format(ms, &[("dateStyle", "short"), ("timeStyle", "short")]))
+}
+
+/// Relative within the last hour, the time of day after that. The day is
+/// left to the separators.
+pub fn message_time(ms: f64) -> String {
+    if Date::now() - ms < HOUR_MS {
+        relative(ms)
+    } else {
+        format(ms, &[("hour", "numeric"), ("minute", "2-digit")])
+    }
+}
+
+/// Date and time in full, for tooltips.
+pub fn full(ms: f64) -> String {
+    format(ms, &[("dateStyle", "full"), ("timeStyle", "short")])
+}
+
+/// "Today", "Yesterday", or the date, in the locale.
+pub fn day_label(ms: f64) -> String {
+    let now = Date::now();
+    let day = local_day(ms);
+    let named = if day == local_day(now) {
+        format_relative(0.0, "day")
+    } else if day == local_day(now - DAY_MS) {
+        format_relative(-1.0, "day")
+    } else {
+        None
+    };
+    if let Some(named) = named {
+        capitalize(&named)
+    } else if day.0 == local_day(now).0 {
+        format(ms, &[("weekday", "long"), ("month", "long"), ("day", "numeric")])
+    } else {
+        format(ms, &[("month", "long"), ("day", "numeric"), ("year", "numeric")])
+    }
+}
+
+pub fn same_day(a: f64, b: f64) -> bool {
+    local_day(a) == local_day(b)
+}
+
+/// Year, month and day in the local time zone.
+fn local_day(ms: f64) -> (u32, u32, u32) {
+    let date = Date::new(&JsValue::from_f64(ms));
+    (date.get_full_year(), date.get_month(), date.get_date())
+}
+
+/// Formats `value` `unit`s from now with `Intl.RelativeTimeFormat` in the
+/// default locale, saying "yesterday" rather than "1 day ago". The js-sys
+/// we are on has no binding for it, hence the reflection. `None` where the
+/// browser lacks it.
+fn format_relative(value: f64, unit: &str) -> Option<String> {
+    let intl = Reflect::get(&js_sys::global(), &JsValue::from_str("Intl")).ok()?;
+    let constructor: Function = Reflect::get(&intl, &JsValue::from_str("RelativeTimeFormat"))
+        .ok()?
+        .dyn_into()
+        .ok()?;
+    let options = Object::new();
+    Reflect::set(&options, &JsValue::from_str("numeric"), &JsValue::from_str("auto")).ok()?;
+    let formatter = Reflect::construct(&constructor, &Array::of2(&Array::new(), &options)).ok()?;
+    let format: Function = Reflect::get(&formatter, &JsValue::from_str("format")).ok()?.dyn_into().ok()?;
+    format
+        .call2(&formatter, &JsValue::from_f64(value), &JsValue::from_str(unit))
+        .ok()?
+        .as_string()
+}
+
+/// "today" becomes "Today", for labels standing on their own.
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Formats with `Intl.DateTimeFormat` in the default locale.
+fn format(ms: f64, options: &[(&str, &str)]) -> String {
+    let object = Object::new();
+    for (key, value) in options {
+        let _ = Reflect::set(&object, &JsValue::from_str(key), &JsValue::from_str(value));
+    }
+    let formatter = Intl::DateTimeFormat::new(&Array::new(), &object);
+    formatter
+        .format()
+        .call1(&JsValue::NULL, &Date::new(&JsValue::from_f64(ms)))
+        .ok()
+        .and_then(|formatted| formatted.as_string())
+        .unwrap_or_default()
+}
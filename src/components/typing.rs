@@ -0,0 +1,115 @@
+use std::collections::{BTreeMap, HashMap};
+
+use yew::prelude::*;
+
+use crate::protocol::{ClientFrame, Conversation};
+
+/// While typing, repeat the `typing` frame this often.
+const RESEND_EVERY_MS: f64 = 3_000.0;
+/// Without a keystroke for this long we tell others we stopped.
+pub const STOP_AFTER_MS: u32 = 4_000;
+/// Forget that someone is typing when they have not repeated it for this
+/// long, e.g. because they closed the tab.
+const EXPIRE_AFTER_MS: f64 = 6_000.0;
+
+/// Decides when our own `typing` frames go out, so that every keystroke
+/// does not turn into a frame.
+#[derive(Default)]
+pub struct TypingNotifier {
+    /// Where we last said we are typing, if we still are.
+    conversation: Option<Conversation>,
+    last_sent: f64,
+}
+
+impl TypingNotifier {
+    /// The frame to send after the composer for `conversation` changed, if any.
+    pub fn input(&mut self, conversation: &Conversation, empty: bool) -> Option<ClientFrame> {
+        if empty {
+            return self.stop();
+        }
+        let now = js_sys::Date::now();
+        if self.conversation.as_ref() == Some(conversation) && now - self.last_sent < RESEND_EVERY_MS {
+            return None;
+        }
+        self.conversation = Some(conversation.clone());
+        self.last_sent = now;
+        Some(ClientFrame::Typing {
+            conversation: conversation.clone(),
+            typing: true,
+        })
+    }
+
+    /// The frame withdrawing the last `typing`, if there was one.
+    pub fn stop(&mut self) -> Option<ClientFrame> {
+        self.conversation.take().map(|conversation| ClientFrame::Typing {
+            conversation,
+            typing: false,
+        })
+    }
+}
+
+/// Who is typing where, according to the server.
+#[derive(Default)]
+pub struct TypingTracker {
+    /// When each user's `typing` expires, per conversation.
+    typing: HashMap<Conversation, BTreeMap<String, f64>>,
+}
+
+impl TypingTracker {
+    pub fn update(&mut self, conversation: Conversation, user: String, typing: bool) {
+        if typing {
+            let expires = js_sys::Date::now() + EXPIRE_AFTER_MS;
+            self.typing.entry(conversation).or_default().insert(user, expires);
+        } else {
+            self.stopped(&conversation, &user);
+        }
+    }
+
+    pub fn stopped(&mut self, conversation: &Conversation, user: &str) {
+        if let Some(users) = self.typing.get_mut(conversation) {
+            users.remove(user);
+        }
+    }
+
+    /// Drops whoever expired. Returns in how many milliseconds the next
+    /// one will, if anyone is left.
+    pub fn expire(&mut self) -> Option<f64> {
+        let now = js_sys::Date::now();
+        for users in self.typing.values_mut() {
+            users.retain(|_, expires| *expires > now);
+        }
+        self.typing.retain(|_, users| !users.is_empty());
+        self.typing
+            .values()
+            .flat_map(|users| users.values())
+            .map(|expires| expires - now)
+            .reduce(f64::min)
+    }
+
+    /// "alice and bob are typing…" above the composer, with the animated
+    /// dots from `index.html`.
+    pub fn view(&self, conversation: &Conversation) -> Html {
+        let names: Vec<&str> = match self.typing.get(conversation) {
+            Some(users) => users.keys().map(String::as_str).collect(),
+            None => vec![],
+        };
+        let label = match names.as_slice() {
+            [] => return html! { <div class="h-5 mb-2"></div> },
+            [one] => format!("{} is typing…", one),
+            [first, second] => format!("{} and {} are typing…", first, second),
+            [first, second, third] => format!("{}, {} and {} are typing…", first, second, third),
+            _ => "Several people are typing…".to_owned(),
+        };
+
+        html! {
+            <div class="h-5 mb-2 flex items-center space-x-2 max-w-6xl mx-auto text-xs text-slate-400 font-mono">
+                <span class="flex items-center space-x-1">
+                    <span class="typing-indicator"></span>
+                    <span class="typing-indicator"></span>
+                    <span class="typing-indicator"></span>
+                </span>
+                <span>{label}</span>
+            </div>
+        }
+    }
+}
@@ -1,6 +1,9 @@
 #![recursion_limit = "512"]
+// The yew 0.19 `html!` macro expands component props into code that trips these.
+#![allow(clippy::unnecessary_operation, clippy::let_unit_value)]
 
 mod components;
+mod protocol;
 mod services;
 
 use std::cell::RefCell;
@@ -13,6 +16,9 @@ use yew_router::prelude::*;
 
 use components::chat::Chat;
 use components::login::Login;
+use protocol::Conversation;
+use services::session::Session;
+use services::websocket::Registered;
 
 // When the `wee_alloc` feature is enabled, this uses `wee_alloc` as the global
 // allocator.
@@ -22,12 +28,16 @@ use components::login::Login;
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
-#[derive(Debug, Clone, Copy, PartialEq, Routable)]
+#[derive(Debug, Clone, PartialEq, Routable)]
 pub enum Route {
     #[at("/")]
     Login,
     #[at("/chat")]
     Chat,
+    #[at("/chat/:room")]
+    ChatRoom { room: String },
+    #[at("/dm/:username")]
+    Dm { username: String },
     #[not_found]
     #[at("/404")]
     NotFound,
@@ -37,14 +47,18 @@ pub type User = Rc<UserInner>;
 
 #[derive(Debug, PartialEq)]
 pub struct UserInner {
-    pub username: RefCell<String>,
+    /// `None` until someone logs in.
+    pub session: RefCell<Option<Session>>,
+    /// Set by the login screen once registered, taken over by the chat.
+    pub connection: RefCell<Option<Registered>>,
 }
 
 #[function_component(Main)]
 fn main() -> Html {
     let ctx = use_state(|| {
         Rc::new(UserInner {
-            username: RefCell::new("initial".into()),
+            session: RefCell::new(Session::load()),
+            connection: RefCell::new(None),
         })
     });
 
@@ -62,11 +76,39 @@ fn main() -> Html {
 fn switch(selected_route: &Route) -> Html {
     match selected_route {
         Route::Login => html! {<Login />},
-        Route::Chat => html! {<Chat/>},
+        Route::Chat => html! {<ChatPage />},
+        Route::ChatRoom { room } => html! {<ChatPage conversation={Conversation::Room(room.clone())}/>},
+        Route::Dm { username } => html! {<ChatPage conversation={Conversation::Direct(username.clone())}/>},
         Route::NotFound => html! {<h1>{"404 baby"}</h1>},
     }
 }
 
+#[derive(Properties, PartialEq)]
+struct ChatPageProps {
+    #[prop_or_default]
+    conversation: Option<Conversation>,
+}
+
+/// Sends visitors without a session to the login screen, and `/chat` to the
+/// conversation they had open last.
+#[function_component(ChatPage)]
+fn chat_page(props: &ChatPageProps) -> Html {
+    let user = use_context::<User>().expect("No context found.");
+    let session = user.session.borrow();
+
+    match &*session {
+        Some(session) => {
+            let conversation = props
+                .conversation
+                .clone()
+                .or_else(|| session.preferences.last_conversation.clone())
+                .unwrap_or_else(Conversation::default_room);
+            html! {<Chat {conversation}/>}
+        }
+        None => html! {<Redirect<Route> to={Route::Login}/>},
+    }
+}
+
 #[wasm_bindgen]
 pub fn run_app() -> Result<(), JsValue> {
     wasm_logger::init(wasm_logger::Config::default());
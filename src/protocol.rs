@@ -0,0 +1,914 @@
+//! Wire format shared with the chat server.
+//!
+//! Every frame is a JSON object tagged by `messageType`. The field names on
+//! the wire are kept compatible with the original server, which is why most
+//! payloads travel under `data`.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Sent with `register` so the server can refuse clients it does not
+/// understand. Bump whenever a frame changes shape.
+pub const PROTOCOL_VERSION: u32 = 15;
+
+/// Room every client is in, and the one legacy servers implicitly use.
+pub const DEFAULT_ROOM: &str = "general";
+
+/// Frames sent from the client to the server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "messageType", rename_all = "lowercase")]
+pub enum ClientFrame {
+    Register {
+        #[serde(rename = "data")]
+        username: String,
+        #[serde(default = "legacy_version")]
+        version: u32,
+        /// Session token from the auth endpoint, proving we own `username`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+    },
+    /// Sent on logout so the server drops us from the user list right away.
+    Unregister,
+    Message {
+        #[serde(rename = "data")]
+        text: String,
+        #[serde(rename = "clientId")]
+        client_id: String,
+        #[serde(default = "default_room")]
+        room: String,
+        #[serde(rename = "replyTo", default, skip_serializing_if = "Option::is_none")]
+        reply_to: Option<String>,
+        #[serde(rename = "inThread", default, skip_serializing_if = "std::ops::Not::not")]
+        in_thread: bool,
+        /// Ids of finished uploads to attach.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        attachments: Vec<String>,
+    },
+    /// A message addressed to a single user.
+    Direct {
+        to: String,
+        #[serde(rename = "data")]
+        text: String,
+        #[serde(rename = "clientId")]
+        client_id: String,
+        #[serde(rename = "replyTo", default, skip_serializing_if = "Option::is_none")]
+        reply_to: Option<String>,
+        #[serde(rename = "inThread", default, skip_serializing_if = "std::ops::Not::not")]
+        in_thread: bool,
+        /// Ids of finished uploads to attach.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        attachments: Vec<String>,
+    },
+    Join {
+        room: String,
+    },
+    Leave {
+        room: String,
+    },
+    /// Asks for a `rooms` frame.
+    ListRooms,
+    /// Tells everyone how available we are.
+    Presence {
+        status: Presence,
+    },
+    /// Whether we are composing a message in `conversation`. Repeated while
+    /// typing, since receivers forget about it after a few seconds.
+    Typing {
+        conversation: Conversation,
+        typing: bool,
+    },
+    /// Moves our read cursor in `conversation` to `message_id`, the newest
+    /// message we have seen.
+    Read {
+        conversation: Conversation,
+        #[serde(rename = "messageId")]
+        message_id: String,
+    },
+    /// Asks for up to `limit` messages older than the message `before`, or
+    /// the latest ones when `before` is `None`.
+    History {
+        conversation: Conversation,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        before: Option<String>,
+        limit: u32,
+    },
+    /// Replaces the text of one of our own messages.
+    Edit {
+        conversation: Conversation,
+        #[serde(rename = "messageId")]
+        message_id: String,
+        text: String,
+    },
+    /// Deletes one of our own messages.
+    Delete {
+        conversation: Conversation,
+        #[serde(rename = "messageId")]
+        message_id: String,
+    },
+    /// Adds our `emoji` to a message, or takes it back.
+    Reaction {
+        conversation: Conversation,
+        #[serde(rename = "messageId")]
+        message_id: String,
+        emoji: String,
+        add: bool,
+    },
+    /// Asks for a `thread` frame with every reply to `message_id`.
+    Thread {
+        conversation: Conversation,
+        #[serde(rename = "messageId")]
+        message_id: String,
+    },
+    /// Takes the preview of `url` off one of our own messages. Answered
+    /// with an `unfurl` frame carrying the previews that are left.
+    DismissPreview {
+        conversation: Conversation,
+        #[serde(rename = "messageId")]
+        message_id: String,
+        url: String,
+    },
+    /// Starts uploading a file of `size` bytes, or resumes it after a
+    /// reconnect. The server answers with `uploadprogress`, and the file
+    /// follows in binary frames, see [`UploadChunk`].
+    Upload {
+        conversation: Conversation,
+        #[serde(rename = "uploadId")]
+        upload_id: String,
+        name: String,
+        #[serde(rename = "contentType")]
+        content_type: String,
+        size: u64,
+    },
+    /// Stops an upload. The server throws away what it got.
+    CancelUpload {
+        #[serde(rename = "uploadId")]
+        upload_id: String,
+    },
+}
+
+/// Frames sent from the server to the client.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "messageType", rename_all = "lowercase")]
+pub enum ServerFrame {
+    Users {
+        #[serde(rename = "dataArray", default)]
+        users: Vec<String>,
+    },
+    Message {
+        #[serde(rename = "data", with = "embedded_json")]
+        message: Box<ChatMessage>,
+    },
+    /// A message only the sender and recipient get to see.
+    Direct {
+        #[serde(rename = "data", with = "embedded_json")]
+        message: Box<ChatMessage>,
+    },
+    Ack {
+        #[serde(rename = "data")]
+        client_id: String,
+    },
+    /// `user` started or stopped composing. A direct conversation arrives
+    /// as `Direct(user)`, as seen from our side.
+    Typing {
+        user: String,
+        conversation: Conversation,
+        typing: bool,
+    },
+    Rooms {
+        rooms: Vec<String>,
+    },
+    /// Someone's availability changed. Also sent when a user goes offline,
+    /// with the time they were last around.
+    Presence {
+        user: String,
+        status: Presence,
+        #[serde(rename = "lastSeen", default, skip_serializing_if = "Option::is_none")]
+        last_seen: Option<String>,
+    },
+    /// A page of past messages, oldest first, answering a `history` frame.
+    History {
+        conversation: Conversation,
+        #[serde(default)]
+        before: Option<String>,
+        messages: Vec<ChatMessage>,
+        #[serde(rename = "hasMore", default)]
+        has_more: bool,
+        /// Read cursor of each member, by username.
+        #[serde(rename = "readBy", default)]
+        read_by: BTreeMap<String, String>,
+    },
+    /// `user` has seen everything up to `message_id`. Our own cursor comes
+    /// back this way too when it moves on another device.
+    Read {
+        user: String,
+        conversation: Conversation,
+        #[serde(rename = "messageId")]
+        message_id: String,
+    },
+    /// A message was edited. Carries the whole message, previous versions
+    /// included.
+    Edit {
+        message: Box<ChatMessage>,
+    },
+    /// A message was deleted. It stays in the timeline as a tombstone so
+    /// that replies to it still have something to point at.
+    Delete {
+        conversation: Conversation,
+        #[serde(rename = "messageId")]
+        message_id: String,
+    },
+    /// `user` reacted to a message with `emoji`, or took it back.
+    Reaction {
+        user: String,
+        conversation: Conversation,
+        #[serde(rename = "messageId")]
+        message_id: String,
+        emoji: String,
+        add: bool,
+    },
+    /// Every reply to `message_id`, oldest first, answering a `thread`
+    /// frame.
+    Thread {
+        conversation: Conversation,
+        #[serde(rename = "messageId")]
+        message_id: String,
+        messages: Vec<ChatMessage>,
+    },
+    /// Previews of the links in a message, sent once the server fetched
+    /// them, and again whenever one is dismissed. Replaces any the message
+    /// had.
+    Unfurl {
+        conversation: Conversation,
+        #[serde(rename = "messageId")]
+        message_id: String,
+        previews: Vec<LinkPreview>,
+    },
+    /// How much of an upload the server has, which is where the next chunk
+    /// starts. Sent after `upload` and after every chunk.
+    UploadProgress {
+        #[serde(rename = "uploadId")]
+        upload_id: String,
+        received: u64,
+    },
+    /// An upload is complete and can be attached to a message.
+    Uploaded {
+        #[serde(rename = "uploadId")]
+        upload_id: String,
+        attachment: Attachment,
+    },
+    /// The server gave up on an upload, e.g. because it is over its limit.
+    UploadFailed {
+        #[serde(rename = "uploadId")]
+        upload_id: String,
+        reason: String,
+    },
+    Error {
+        #[serde(rename = "data")]
+        reason: String,
+        /// Machine readable cause, `None` from legacy servers.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<ErrorCode>,
+    },
+    /// A frame type introduced by a newer server. Ignored rather than
+    /// treated as a parse error.
+    #[serde(other)]
+    Unknown,
+}
+
+/// How available a user is. Legacy servers only ever tell us who is
+/// online, through `users`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Presence {
+    Online,
+    Away,
+    #[serde(rename = "dnd")]
+    DoNotDisturb,
+    Offline,
+}
+
+/// Why the server refused something, mostly a `register` or a request to
+/// the auth endpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    NameTaken,
+    InvalidName,
+    ServerFull,
+    /// Unknown user or wrong password.
+    InvalidCredentials,
+    /// The session token is missing, expired or revoked.
+    Unauthorized,
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    /// Assigned by the server. Messages from legacy servers have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub from: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    #[serde(default, rename = "clientId", skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default = "default_room")]
+    pub room: String,
+    /// Recipient of a direct message, `None` for room messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    /// When the current text was written, if it was edited.
+    #[serde(default, rename = "editedAt", skip_serializing_if = "Option::is_none")]
+    pub edited_at: Option<String>,
+    /// Earlier versions of the text, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub edits: Vec<Revision>,
+    /// Deleted by its sender. The text and edits are gone.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deleted: bool,
+    /// Who reacted with which emoji, in the order they did.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reactions: BTreeMap<String, Vec<String>>,
+    /// Id of the message this one answers.
+    #[serde(default, rename = "replyTo", skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    /// Posted to the thread of `reply_to` only, it does not show up in the
+    /// conversation itself.
+    #[serde(default, rename = "inThread", skip_serializing_if = "std::ops::Not::not")]
+    pub in_thread: bool,
+    /// How many messages answer this one.
+    #[serde(default, rename = "replyCount", skip_serializing_if = "is_zero")]
+    pub reply_count: u32,
+    /// What the pages the message links to are about, see
+    /// [`ServerFrame::Unfurl`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previews: Vec<LinkPreview>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+}
+
+/// A version of a message's text that was since edited.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Revision {
+    pub message: String,
+    /// When this version was written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+}
+
+/// Metadata the server found at a link. Any of it may be missing.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LinkPreview {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Thumbnail to show next to the text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+/// A file shared in a message.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "contentType")]
+    pub content_type: String,
+    pub size: u64,
+    /// Where to download it.
+    pub url: String,
+    /// A smaller version of an image, for the message list.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
+}
+
+/// A piece of an upload. Goes out as a binary frame: the length of the
+/// upload id in one byte, the id, the offset of the piece as a big-endian
+/// `u64`, then the bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UploadChunk {
+    pub upload_id: String,
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+impl UploadChunk {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let id = self.upload_id.as_bytes();
+        let id_len = u8::try_from(id.len()).expect("upload ids are short");
+        let mut bytes = Vec::with_capacity(1 + id.len() + 8 + self.data.len());
+        bytes.push(id_len);
+        bytes.extend_from_slice(id);
+        bytes.extend_from_slice(&self.offset.to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+/// Where a message lives: a named room, or the direct thread with a peer
+/// as seen from the local user's side.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Conversation {
+    Room(String),
+    Direct(String),
+}
+
+impl Conversation {
+    pub fn default_room() -> Self {
+        Conversation::Room(DEFAULT_ROOM.to_owned())
+    }
+}
+
+impl ChatMessage {
+    /// The conversation this message belongs to for the user `me`.
+    pub fn conversation(&self, me: &str) -> Conversation {
+        match &self.to {
+            Some(to) if self.from == me => Conversation::Direct(to.clone()),
+            Some(_) => Conversation::Direct(self.from.clone()),
+            None => Conversation::Room(self.room.clone()),
+        }
+    }
+
+    pub fn edited(&self) -> bool {
+        self.edited_at.is_some() || !self.edits.is_empty()
+    }
+
+    /// Turns the message into a tombstone: who sent it and when stays, what
+    /// it said does not. Its place in threads stays too.
+    pub fn tombstone(&mut self) {
+        self.message.clear();
+        self.edits.clear();
+        self.edited_at = None;
+        self.reactions.clear();
+        self.previews.clear();
+        self.attachments.clear();
+        self.deleted = true;
+    }
+
+    pub fn reacted(&self, user: &str, emoji: &str) -> bool {
+        self.reactions
+            .get(emoji)
+            .is_some_and(|users| users.iter().any(|u| u == user))
+    }
+
+    /// Adds or takes back `user`'s `emoji`. Returns whether that changed
+    /// anything, so the echo of our own reaction is a no-op.
+    pub fn react(&mut self, user: &str, emoji: &str, add: bool) -> bool {
+        if self.reacted(user, emoji) == add {
+            return false;
+        }
+        let users = self.reactions.entry(emoji.to_owned()).or_default();
+        if add {
+            users.push(user.to_owned());
+        } else {
+            users.retain(|u| u != user);
+            if users.is_empty() {
+                self.reactions.remove(emoji);
+            }
+        }
+        true
+    }
+}
+
+impl ClientFrame {
+    pub fn register(username: impl Into<String>, token: Option<String>) -> Self {
+        ClientFrame::Register {
+            username: username.into(),
+            version: PROTOCOL_VERSION,
+            token,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("client frames always serialize")
+    }
+}
+
+impl ServerFrame {
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+}
+
+fn legacy_version() -> u32 {
+    0
+}
+
+fn default_room() -> String {
+    DEFAULT_ROOM.to_owned()
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// The original server double-encodes some payloads as a JSON string inside
+/// the frame. Accept both that and a plain object, always write the string.
+mod embedded_json {
+    use serde::de::{DeserializeOwned, Error as _};
+    use serde::ser::Error as _;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<T: Serialize, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        let json = serde_json::to_string(value).map_err(S::Error::custom)?;
+        serializer.serialize_str(&json)
+    }
+
+    pub fn deserialize<'de, T: DeserializeOwned, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw<T> {
+            Embedded(String),
+            Inline(T),
+        }
+
+        match Raw::<T>::deserialize(deserializer)? {
+            Raw::Embedded(json) => serde_json::from_str(&json).map_err(D::Error::custom),
+            Raw::Inline(value) => Ok(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room() -> Conversation {
+        Conversation::default_room()
+    }
+
+    fn attachment() -> Attachment {
+        Attachment {
+            id: "a1".to_owned(),
+            name: "cat.png".to_owned(),
+            content_type: "image/png".to_owned(),
+            size: 1234,
+            url: "https://files.example.com/a1".to_owned(),
+            thumbnail: Some("https://files.example.com/a1/small".to_owned()),
+        }
+    }
+
+    /// A message with every optional field set.
+    fn message() -> ChatMessage {
+        ChatMessage {
+            id: Some("m2".to_owned()),
+            from: "alice".to_owned(),
+            message: "hello".to_owned(),
+            timestamp: Some("2024-05-01T10:00:00Z".to_owned()),
+            client_id: Some("c1".to_owned()),
+            room: "general".to_owned(),
+            to: Some("bob".to_owned()),
+            edited_at: Some("2024-05-01T10:05:00Z".to_owned()),
+            edits: vec![Revision {
+                message: "helo".to_owned(),
+                timestamp: Some("2024-05-01T10:00:00Z".to_owned()),
+            }],
+            deleted: false,
+            reactions: BTreeMap::from([("👍".to_owned(), vec!["bob".to_owned()])]),
+            reply_to: Some("m1".to_owned()),
+            in_thread: true,
+            reply_count: 3,
+            previews: vec![LinkPreview {
+                url: "https://example.com".to_owned(),
+                title: Some("Example".to_owned()),
+                description: None,
+                image: None,
+            }],
+            attachments: vec![attachment()],
+        }
+    }
+
+    fn legacy_message() -> ChatMessage {
+        ChatMessage {
+            id: None,
+            from: "alice".to_owned(),
+            message: "hi".to_owned(),
+            timestamp: None,
+            client_id: None,
+            room: DEFAULT_ROOM.to_owned(),
+            to: None,
+            edited_at: None,
+            edits: vec![],
+            deleted: false,
+            reactions: BTreeMap::new(),
+            reply_to: None,
+            in_thread: false,
+            reply_count: 0,
+            previews: vec![],
+            attachments: vec![],
+        }
+    }
+
+    fn assert_client_round_trip(frame: ClientFrame) {
+        let json = frame.to_json();
+        let back: ClientFrame = serde_json::from_str(&json).unwrap_or_else(|e| panic!("{}: {}", json, e));
+        assert_eq!(back, frame, "{}", json);
+    }
+
+    fn assert_server_round_trip(frame: ServerFrame) {
+        let json = serde_json::to_string(&frame).unwrap();
+        let back = ServerFrame::from_json(&json).unwrap_or_else(|e| panic!("{}: {}", json, e));
+        assert_eq!(back, frame, "{}", json);
+    }
+
+    #[test]
+    fn client_frames_round_trip() {
+        let frames = vec![
+            ClientFrame::register("alice", Some("token".to_owned())),
+            ClientFrame::register("alice", None),
+            ClientFrame::Unregister,
+            ClientFrame::Message {
+                text: "hi".to_owned(),
+                client_id: "c1".to_owned(),
+                room: "rust".to_owned(),
+                reply_to: Some("m1".to_owned()),
+                in_thread: true,
+                attachments: vec!["a1".to_owned()],
+            },
+            ClientFrame::Message {
+                text: "hi".to_owned(),
+                client_id: "c1".to_owned(),
+                room: DEFAULT_ROOM.to_owned(),
+                reply_to: None,
+                in_thread: false,
+                attachments: vec![],
+            },
+            ClientFrame::Direct {
+                to: "bob".to_owned(),
+                text: "hi".to_owned(),
+                client_id: "c2".to_owned(),
+                reply_to: Some("m1".to_owned()),
+                in_thread: false,
+                attachments: vec!["a1".to_owned(), "a2".to_owned()],
+            },
+            ClientFrame::Join { room: "rust".to_owned() },
+            ClientFrame::Leave { room: "rust".to_owned() },
+            ClientFrame::ListRooms,
+            ClientFrame::Presence {
+                status: Presence::DoNotDisturb,
+            },
+            ClientFrame::Typing {
+                conversation: Conversation::Direct("bob".to_owned()),
+                typing: true,
+            },
+            ClientFrame::Read {
+                conversation: room(),
+                message_id: "m1".to_owned(),
+            },
+            ClientFrame::History {
+                conversation: room(),
+                before: Some("m1".to_owned()),
+                limit: 50,
+            },
+            ClientFrame::History {
+                conversation: room(),
+                before: None,
+                limit: 50,
+            },
+            ClientFrame::Edit {
+                conversation: room(),
+                message_id: "m1".to_owned(),
+                text: "fixed".to_owned(),
+            },
+            ClientFrame::Delete {
+                conversation: room(),
+                message_id: "m1".to_owned(),
+            },
+            ClientFrame::Reaction {
+                conversation: room(),
+                message_id: "m1".to_owned(),
+                emoji: "🎉".to_owned(),
+                add: false,
+            },
+            ClientFrame::Thread {
+                conversation: room(),
+                message_id: "m1".to_owned(),
+            },
+            ClientFrame::DismissPreview {
+                conversation: room(),
+                message_id: "m1".to_owned(),
+                url: "https://example.com".to_owned(),
+            },
+            ClientFrame::Upload {
+                conversation: room(),
+                upload_id: "u1".to_owned(),
+                name: "cat.png".to_owned(),
+                content_type: "image/png".to_owned(),
+                size: 1234,
+            },
+            ClientFrame::CancelUpload {
+                upload_id: "u1".to_owned(),
+            },
+        ];
+        for frame in frames {
+            assert_client_round_trip(frame);
+        }
+    }
+
+    #[test]
+    fn server_frames_round_trip() {
+        let frames = vec![
+            ServerFrame::Users {
+                users: vec!["alice".to_owned(), "bob".to_owned()],
+            },
+            ServerFrame::Message {
+                message: Box::new(message()),
+            },
+            ServerFrame::Message {
+                message: Box::new(legacy_message()),
+            },
+            ServerFrame::Direct {
+                message: Box::new(message()),
+            },
+            ServerFrame::Ack {
+                client_id: "c1".to_owned(),
+            },
+            ServerFrame::Typing {
+                user: "bob".to_owned(),
+                conversation: Conversation::Direct("bob".to_owned()),
+                typing: false,
+            },
+            ServerFrame::Rooms {
+                rooms: vec!["general".to_owned(), "rust".to_owned()],
+            },
+            ServerFrame::Presence {
+                user: "bob".to_owned(),
+                status: Presence::Offline,
+                last_seen: Some("2024-05-01T09:00:00Z".to_owned()),
+            },
+            ServerFrame::History {
+                conversation: room(),
+                before: Some("m9".to_owned()),
+                messages: vec![legacy_message(), message()],
+                has_more: true,
+                read_by: BTreeMap::from([("bob".to_owned(), "m2".to_owned())]),
+            },
+            ServerFrame::Read {
+                user: "bob".to_owned(),
+                conversation: room(),
+                message_id: "m2".to_owned(),
+            },
+            ServerFrame::Edit {
+                message: Box::new(message()),
+            },
+            ServerFrame::Delete {
+                conversation: room(),
+                message_id: "m2".to_owned(),
+            },
+            ServerFrame::Reaction {
+                user: "bob".to_owned(),
+                conversation: room(),
+                message_id: "m2".to_owned(),
+                emoji: "👍".to_owned(),
+                add: true,
+            },
+            ServerFrame::Thread {
+                conversation: room(),
+                message_id: "m1".to_owned(),
+                messages: vec![message()],
+            },
+            ServerFrame::Unfurl {
+                conversation: room(),
+                message_id: "m2".to_owned(),
+                previews: message().previews,
+            },
+            ServerFrame::UploadProgress {
+                upload_id: "u1".to_owned(),
+                received: 65536,
+            },
+            ServerFrame::Uploaded {
+                upload_id: "u1".to_owned(),
+                attachment: attachment(),
+            },
+            ServerFrame::UploadFailed {
+                upload_id: "u1".to_owned(),
+                reason: "Too large".to_owned(),
+            },
+            ServerFrame::Error {
+                reason: "Name taken".to_owned(),
+                code: Some(ErrorCode::NameTaken),
+            },
+            ServerFrame::Error {
+                reason: "Oops".to_owned(),
+                code: None,
+            },
+            ServerFrame::Unknown,
+        ];
+        for frame in frames {
+            assert_server_round_trip(frame);
+        }
+    }
+
+    #[test]
+    fn messages_are_accepted_embedded_or_inline() {
+        let embedded = r#"{"messageType":"message","data":"{\"from\":\"alice\",\"message\":\"hi\"}"}"#;
+        let inline = r#"{"messageType":"message","data":{"from":"alice","message":"hi"}}"#;
+        let expected = ServerFrame::Message {
+            message: Box::new(legacy_message()),
+        };
+        assert_eq!(ServerFrame::from_json(embedded).unwrap(), expected);
+        assert_eq!(ServerFrame::from_json(inline).unwrap(), expected);
+
+        let embedded = r#"{"messageType":"direct","data":"{\"from\":\"alice\",\"message\":\"hi\",\"to\":\"bob\"}"}"#;
+        let inline = r#"{"messageType":"direct","data":{"from":"alice","message":"hi","to":"bob"}}"#;
+        let expected = ServerFrame::Direct {
+            message: Box::new(ChatMessage {
+                to: Some("bob".to_owned()),
+                ..legacy_message()
+            }),
+        };
+        assert_eq!(ServerFrame::from_json(embedded).unwrap(), expected);
+        assert_eq!(ServerFrame::from_json(inline).unwrap(), expected);
+    }
+
+    #[test]
+    fn messages_are_written_embedded() {
+        let json = serde_json::to_value(ServerFrame::Message {
+            message: Box::new(legacy_message()),
+        })
+        .unwrap();
+        assert!(json["data"].is_string(), "{}", json);
+    }
+
+    #[test]
+    fn legacy_users_frame() {
+        let frame = ServerFrame::from_json(r#"{"messageType":"users","dataArray":["alice","bob"]}"#).unwrap();
+        assert_eq!(
+            frame,
+            ServerFrame::Users {
+                users: vec!["alice".to_owned(), "bob".to_owned()]
+            }
+        );
+        let frame = ServerFrame::from_json(r#"{"messageType":"users"}"#).unwrap();
+        assert_eq!(frame, ServerFrame::Users { users: vec![] });
+    }
+
+    #[test]
+    fn legacy_register_has_version_zero() {
+        let frame: ClientFrame = serde_json::from_str(r#"{"messageType":"register","data":"alice"}"#).unwrap();
+        assert_eq!(
+            frame,
+            ClientFrame::Register {
+                username: "alice".to_owned(),
+                version: 0,
+                token: None,
+            }
+        );
+    }
+
+    #[test]
+    fn register_carries_the_protocol_version() {
+        let json: serde_json::Value =
+            serde_json::from_str(&ClientFrame::register("alice", None).to_json()).unwrap();
+        assert_eq!(json["version"], PROTOCOL_VERSION);
+        assert_eq!(json["data"], "alice");
+        assert!(json.get("token").is_none());
+    }
+
+    #[test]
+    fn unknown_frames_are_not_errors() {
+        let frame = ServerFrame::from_json(r#"{"messageType":"hologram","data":{"x":1}}"#).unwrap();
+        assert_eq!(frame, ServerFrame::Unknown);
+    }
+
+    #[test]
+    fn unknown_error_codes_become_other() {
+        let frame = ServerFrame::from_json(r#"{"messageType":"error","data":"No","code":"quota_exceeded"}"#).unwrap();
+        assert_eq!(
+            frame,
+            ServerFrame::Error {
+                reason: "No".to_owned(),
+                code: Some(ErrorCode::Other),
+            }
+        );
+    }
+
+    #[test]
+    fn upload_chunk_layout() {
+        let chunk = UploadChunk {
+            upload_id: "up".to_owned(),
+            offset: 0x0102_0304_0506_0708,
+            data: vec![0xAA, 0xBB],
+        };
+        assert_eq!(
+            chunk.to_bytes(),
+            vec![2, b'u', b'p', 1, 2, 3, 4, 5, 6, 7, 8, 0xAA, 0xBB]
+        );
+    }
+
+    #[test]
+    fn empty_upload_chunk_layout() {
+        let chunk = UploadChunk {
+            upload_id: String::new(),
+            offset: 65536,
+            data: vec![],
+        };
+        assert_eq!(chunk.to_bytes(), vec![0, 0, 0, 0, 0, 0, 1, 0, 0]);
+    }
+}
@@ -0,0 +1,81 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use gloo::events::EventListener;
+use gloo::timers::callback::Interval;
+use gloo::utils::{document, window};
+use yew::Callback;
+
+/// Without any input for this long the user counts as away.
+const IDLE_AFTER_MS: f64 = 5.0 * 60_000.0;
+/// How often to check for idleness.
+const CHECK_EVERY_MS: u32 = 15_000;
+/// Input that proves someone is at the keyboard.
+const INPUT_EVENTS: [&str; 4] = ["keydown", "mousemove", "pointerdown", "wheel"];
+
+/// Watches page visibility and input to tell when the user stepped away.
+/// Calls back with `true` when they go idle and `false` once they are back,
+/// only on changes.
+pub struct ActivityMonitor {
+    _listeners: Vec<EventListener>,
+    _check: Interval,
+}
+
+impl ActivityMonitor {
+    pub fn new(on_idle: Callback<bool>) -> Self {
+        let state = Rc::new(State {
+            last_input: Cell::new(js_sys::Date::now()),
+            idle: Cell::new(false),
+            on_idle,
+        });
+
+        let mut listeners: Vec<EventListener> = INPUT_EVENTS
+            .iter()
+            .map(|&event| {
+                let state = state.clone();
+                EventListener::new(&window(), event, move |_| state.active())
+            })
+            .collect();
+
+        listeners.push({
+            let state = state.clone();
+            EventListener::new(&document(), "visibilitychange", move |_| {
+                if document().hidden() {
+                    state.set_idle(true);
+                } else {
+                    state.active();
+                }
+            })
+        });
+
+        let check = Interval::new(CHECK_EVERY_MS, move || {
+            if js_sys::Date::now() - state.last_input.get() > IDLE_AFTER_MS {
+                state.set_idle(true);
+            }
+        });
+
+        Self {
+            _listeners: listeners,
+            _check: check,
+        }
+    }
+}
+
+struct State {
+    last_input: Cell<f64>,
+    idle: Cell<bool>,
+    on_idle: Callback<bool>,
+}
+
+impl State {
+    fn active(&self) {
+        self.last_input.set(js_sys::Date::now());
+        self.set_idle(false);
+    }
+
+    fn set_idle(&self, idle: bool) {
+        if self.idle.replace(idle) != idle {
+            self.on_idle.emit(idle);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::ErrorCode;
+#[cfg(not(feature = "mock-server"))]
+use crate::services::config;
+#[cfg(feature = "mock-server")]
+use crate::services::mock_server;
+
+/// Refresh this long before the token actually expires, so a reconnect
+/// never races the expiry.
+const REFRESH_MARGIN_MS: f64 = 60_000.0;
+
+/// What the auth endpoint hands out for a valid username and password.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Token {
+    /// Sent with every `register` frame.
+    #[serde(rename = "token")]
+    pub access: String,
+    /// Trades for a new token once `access` expires.
+    #[serde(rename = "refreshToken")]
+    pub refresh: String,
+    /// Milliseconds since the epoch, like `Date.now()`.
+    #[serde(rename = "expiresAt")]
+    pub expires_at: f64,
+}
+
+impl Token {
+    pub fn expires_soon(&self) -> bool {
+        self.expires_at - js_sys::Date::now() < REFRESH_MARGIN_MS
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AuthError {
+    /// The endpoint answered, but said no.
+    Rejected {
+        reason: String,
+        code: Option<ErrorCode>,
+    },
+    /// The endpoint could not be reached or answered gibberish.
+    Unreachable(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Rejected { reason, .. } => write!(f, "{}", reason),
+            AuthError::Unreachable(e) => write!(f, "Could not reach the server: {}", e),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Credentials<'a> {
+    username: &'a str,
+    password: &'a str,
+}
+
+#[derive(Serialize)]
+struct RefreshRequest<'a> {
+    #[serde(rename = "refreshToken")]
+    refresh_token: &'a str,
+}
+
+/// Body of any non-2xx answer.
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: String,
+    #[serde(default)]
+    code: Option<ErrorCode>,
+}
+
+/// Creates an account and signs it in.
+pub async fn sign_up(server: Option<&str>, username: &str, password: &str) -> Result<Token, AuthError> {
+    exchange(server, "signup", &Credentials { username, password }).await
+}
+
+pub async fn sign_in(server: Option<&str>, username: &str, password: &str) -> Result<Token, AuthError> {
+    exchange(server, "signin", &Credentials { username, password }).await
+}
+
+/// Trades the refresh token for a fresh one. A `Rejected` answer means the
+/// session is over and the user has to sign in again.
+pub async fn refresh(server: Option<&str>, token: &Token) -> Result<Token, AuthError> {
+    let request = RefreshRequest {
+        refresh_token: &token.refresh,
+    };
+    exchange(server, "refresh", &request).await
+}
+
+async fn exchange<T: Serialize>(server: Option<&str>, path: &str, body: &T) -> Result<Token, AuthError> {
+    let body = serde_json::to_string(body).expect("auth requests always serialize");
+    let (status, text) = post(server, path, body).await.map_err(AuthError::Unreachable)?;
+
+    if (200..300).contains(&status) {
+        return serde_json::from_str(&text).map_err(|e| AuthError::Unreachable(e.to_string()));
+    }
+    Err(match serde_json::from_str::<ErrorBody>(&text) {
+        Ok(ErrorBody { error, code }) => AuthError::Rejected { reason: error, code },
+        Err(_) => AuthError::Rejected {
+            reason: format!("The server answered {}", status),
+            code: None,
+        },
+    })
+}
+
+/// POSTs `body` to `/auth/<path>`, returning the status and body.
+#[cfg(not(feature = "mock-server"))]
+async fn post(server: Option<&str>, path: &str, body: String) -> Result<(u16, String), String> {
+    use reqwasm::http::Request;
+
+    let url = format!("{}/auth/{}", config::http_url(server).await, path);
+    let response = Request::post(&url)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let text = response.text().await.map_err(|e| e.to_string())?;
+    Ok((response.status(), text))
+}
+
+/// Answers from the in-browser mock instead, see [`mock_server`].
+#[cfg(feature = "mock-server")]
+async fn post(_server: Option<&str>, path: &str, body: String) -> Result<(u16, String), String> {
+    Ok(mock_server::auth(path, &body))
+}
@@ -0,0 +1,122 @@
+use gloo::utils::{document, window};
+use reqwasm::http::Request;
+use serde::Deserialize;
+use web_sys::UrlSearchParams;
+
+/// Server used when nothing else is configured. Can be baked in at build
+/// time with `YEWCHAT_SERVER=chat.example.com npm run build`.
+const DEFAULT_SERVER: &str = match option_env!("YEWCHAT_SERVER") {
+    Some(server) => server,
+    None => "127.0.0.1:8080",
+};
+
+const QUERY_PARAM: &str = "server";
+const META_NAME: &str = "yewchat-server";
+const CONFIG_URL: &str = "/config.json";
+
+#[derive(Debug, Default, Deserialize)]
+struct ConfigFile {
+    server: Option<String>,
+}
+
+/// Resolves the WebSocket endpoint. The first source that yields a value
+/// wins: the explicit `login_override`, the `?server=` query parameter, a
+/// `<meta name="yewchat-server">` tag, `config.json`, then the default.
+pub async fn server_url(login_override: Option<&str>) -> String {
+    to_ws_url(&server(login_override).await, page_is_secure())
+}
+
+/// Base URL of the HTTP API served next to the WebSocket, resolved the same
+/// way as [`server_url`].
+#[cfg_attr(feature = "mock-server", allow(dead_code))]
+pub async fn http_url(login_override: Option<&str>) -> String {
+    to_http_url(&server(login_override).await, page_is_secure())
+}
+
+async fn server(login_override: Option<&str>) -> String {
+    let configured = login_override
+        .map(str::to_owned)
+        .filter(|s| !s.trim().is_empty())
+        .or_else(from_query)
+        .or_else(from_meta);
+
+    match configured {
+        Some(server) => server,
+        None => from_config_file()
+            .await
+            .unwrap_or_else(|| DEFAULT_SERVER.to_owned()),
+    }
+}
+
+/// Turns whatever the user configured (`host:port`, `http(s)://…` or
+/// `ws(s)://…`) into a WebSocket URL. Secure pages always get `wss://`,
+/// since browsers refuse plain sockets from an HTTPS origin.
+pub fn to_ws_url(server: &str, secure: bool) -> String {
+    let (host, explicitly_secure) = split_scheme(server);
+    let scheme = if secure || explicitly_secure { "wss" } else { "ws" };
+    format!("{}://{}", scheme, host)
+}
+
+/// Like [`to_ws_url`], for plain HTTP requests to the same server.
+#[cfg_attr(feature = "mock-server", allow(dead_code))]
+pub fn to_http_url(server: &str, secure: bool) -> String {
+    let (host, explicitly_secure) = split_scheme(server);
+    let scheme = if secure || explicitly_secure { "https" } else { "http" };
+    format!("{}://{}", scheme, host)
+}
+
+/// Strips any scheme off `server`, telling whether it asked for TLS.
+fn split_scheme(server: &str) -> (&str, bool) {
+    let server = server.trim().trim_end_matches('/');
+    let host = ["wss://", "ws://", "https://", "http://"]
+        .iter()
+        .find_map(|scheme| server.strip_prefix(scheme))
+        .unwrap_or(server);
+    let explicitly_secure = server.starts_with("wss://") || server.starts_with("https://");
+    (host, explicitly_secure)
+}
+
+fn page_is_secure() -> bool {
+    window()
+        .location()
+        .protocol()
+        .map(|protocol| protocol == "https:")
+        .unwrap_or(false)
+}
+
+fn from_query() -> Option<String> {
+    let search = window().location().search().ok()?;
+    let params = UrlSearchParams::new_with_str(&search).ok()?;
+    params.get(QUERY_PARAM).filter(|s| !s.trim().is_empty())
+}
+
+fn from_meta() -> Option<String> {
+    document()
+        .query_selector(&format!("meta[name=\"{}\"]", META_NAME))
+        .ok()
+        .flatten()
+        .and_then(|meta| meta.get_attribute("content"))
+        .filter(|s| !s.trim().is_empty())
+}
+
+async fn from_config_file() -> Option<String> {
+    let response = match Request::get(CONFIG_URL).send().await {
+        Ok(response) if response.ok() => response,
+        Ok(response) => {
+            log::debug!("no {} ({})", CONFIG_URL, response.status());
+            return None;
+        }
+        Err(e) => {
+            log::warn!("Failed to fetch {}: {:?}", CONFIG_URL, e);
+            return None;
+        }
+    };
+
+    match response.json::<ConfigFile>().await {
+        Ok(config) => config.server.filter(|s| !s.trim().is_empty()),
+        Err(e) => {
+            log::warn!("Failed to parse {}: {:?}", CONFIG_URL, e);
+            None
+        }
+    }
+}
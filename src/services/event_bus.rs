@@ -1,47 +1,214 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
 use yew_agent::{Agent, AgentLink, Context, HandlerId};
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::protocol::ServerFrame;
+use crate::services::auth::Token;
+use crate::services::websocket::ConnectionState;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Topic {
+    /// Frames received from the server.
+    Server,
+    /// The WebSocket going up or down.
+    Connection,
+    /// Events raised locally by the UI or services.
+    Ui,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Server(ServerFrame),
+    Connection(ConnectionState),
+    Ui(UiEvent),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum UiEvent {
+    /// Something worth telling the user that did not come from the server,
+    /// e.g. a frame that could not be understood.
+    Notice(String),
+    /// The connection traded its session token for this one.
+    TokenRefreshed(Token),
+    /// The session token can no longer be refreshed, sign in again.
+    SessionExpired,
+}
+
+impl Event {
+    pub fn topic(&self) -> Topic {
+        match self {
+            Event::Server(_) => Topic::Server,
+            Event::Connection(_) => Topic::Connection,
+            Event::Ui(_) => Topic::Ui,
+        }
+    }
+}
+
+#[derive(Debug)]
 pub enum Request {
-    EventBusMsg(String),
+    /// Start receiving events of these topics.
+    Subscribe(Vec<Topic>),
+    /// Stop receiving events of these topics.
+    Unsubscribe(Vec<Topic>),
+    /// Deliver an event to everyone subscribed to its topic.
+    Publish(Event),
 }
 
 pub struct EventBus {
     link: AgentLink<EventBus>,
-    subscribers: HashSet<HandlerId>,
+    subscribers: Subscribers,
 }
 
 impl Agent for EventBus {
     type Reach = Context<Self>;
     type Message = ();
     type Input = Request;
-    type Output = String;
+    type Output = Event;
 
     fn create(link: AgentLink<Self>) -> Self {
         Self {
             link,
-            subscribers: HashSet::new(),
+            subscribers: Subscribers::default(),
         }
     }
 
     fn update(&mut self, _msg: Self::Message) {}
 
-    fn handle_input(&mut self, msg: Self::Input, _id: HandlerId) {
+    fn handle_input(&mut self, msg: Self::Input, id: HandlerId) {
         match msg {
-            Request::EventBusMsg(s) => {
-                for sub in self.subscribers.iter() {
-                    self.link.respond(*sub, s.clone())
+            Request::Subscribe(topics) => self.subscribers.subscribe(id, topics),
+            Request::Unsubscribe(topics) => self.subscribers.unsubscribe(id, &topics),
+            Request::Publish(event) => {
+                for sub in self.subscribers.interested_in(event.topic()) {
+                    self.link.respond(sub, event.clone())
                 }
             }
         }
     }
 
-    fn connected(&mut self, id: HandlerId) {
-        self.subscribers.insert(id);
+    fn disconnected(&mut self, id: HandlerId) {
+        self.subscribers.remove(id);
     }
+}
 
-    fn disconnected(&mut self, id: HandlerId) {
-        self.subscribers.remove(&id);
+/// Which handler listens to which topics. A handler that never subscribed
+/// receives nothing, which is what dispatchers want. Generic over the id so
+/// it can be exercised without the agent runtime handing out `HandlerId`s.
+struct Subscribers<Id = HandlerId> {
+    topics: HashMap<Id, HashSet<Topic>>,
+}
+
+impl<Id> Default for Subscribers<Id> {
+    fn default() -> Self {
+        Self { topics: HashMap::new() }
+    }
+}
+
+impl<Id: Eq + Hash + Copy> Subscribers<Id> {
+    fn subscribe(&mut self, id: Id, topics: Vec<Topic>) {
+        self.topics.entry(id).or_default().extend(topics);
+    }
+
+    fn unsubscribe(&mut self, id: Id, topics: &[Topic]) {
+        if let Some(subscribed) = self.topics.get_mut(&id) {
+            for topic in topics {
+                subscribed.remove(topic);
+            }
+            if subscribed.is_empty() {
+                self.topics.remove(&id);
+            }
+        }
+    }
+
+    fn remove(&mut self, id: Id) {
+        self.topics.remove(&id);
+    }
+
+    fn interested_in(&self, topic: Topic) -> Vec<Id> {
+        self.topics
+            .iter()
+            .filter(|(_, topics)| topics.contains(&topic))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut ids: Vec<u32>) -> Vec<u32> {
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn subscribers_receive_their_topics() {
+        let mut subscribers = Subscribers::default();
+        subscribers.subscribe(1, vec![Topic::Server, Topic::Connection]);
+        subscribers.subscribe(2, vec![Topic::Server]);
+
+        assert_eq!(sorted(subscribers.interested_in(Topic::Server)), vec![1, 2]);
+        assert_eq!(subscribers.interested_in(Topic::Connection), vec![1]);
+        assert!(subscribers.interested_in(Topic::Ui).is_empty());
+    }
+
+    #[test]
+    fn subscribing_again_adds_topics() {
+        let mut subscribers = Subscribers::default();
+        subscribers.subscribe(1, vec![Topic::Server]);
+        subscribers.subscribe(1, vec![Topic::Ui, Topic::Server]);
+
+        assert_eq!(subscribers.interested_in(Topic::Server), vec![1]);
+        assert_eq!(subscribers.interested_in(Topic::Ui), vec![1]);
+    }
+
+    #[test]
+    fn unsubscribing_keeps_other_topics() {
+        let mut subscribers = Subscribers::default();
+        subscribers.subscribe(1, vec![Topic::Server, Topic::Ui]);
+        subscribers.unsubscribe(1, &[Topic::Server]);
+
+        assert!(subscribers.interested_in(Topic::Server).is_empty());
+        assert_eq!(subscribers.interested_in(Topic::Ui), vec![1]);
+    }
+
+    #[test]
+    fn unsubscribing_the_last_topic_forgets_the_handler() {
+        let mut subscribers = Subscribers::default();
+        subscribers.subscribe(1, vec![Topic::Server, Topic::Ui]);
+        subscribers.unsubscribe(1, &[Topic::Server, Topic::Ui]);
+
+        assert!(subscribers.topics.is_empty());
+    }
+
+    #[test]
+    fn unsubscribing_an_unknown_handler_does_nothing() {
+        let mut subscribers = Subscribers::default();
+        subscribers.subscribe(1, vec![Topic::Server]);
+        subscribers.unsubscribe(2, &[Topic::Server]);
+
+        assert_eq!(subscribers.interested_in(Topic::Server), vec![1]);
+    }
+
+    #[test]
+    fn disconnected_handlers_are_removed() {
+        let mut subscribers = Subscribers::default();
+        subscribers.subscribe(1, vec![Topic::Server, Topic::Connection]);
+        subscribers.subscribe(2, vec![Topic::Server]);
+        subscribers.remove(1);
+
+        assert_eq!(subscribers.interested_in(Topic::Server), vec![2]);
+        assert!(subscribers.interested_in(Topic::Connection).is_empty());
+    }
+
+    #[test]
+    fn handlers_that_never_subscribed_receive_nothing() {
+        let mut subscribers = Subscribers::default();
+        subscribers.subscribe(1, vec![Topic::Server]);
+
+        for topic in [Topic::Server, Topic::Connection, Topic::Ui] {
+            assert!(!subscribers.interested_in(topic).contains(&2));
+        }
     }
 }
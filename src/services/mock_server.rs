@@ -0,0 +1,434 @@
+//! Stand-ins for the server's HTTP endpoints, and for the link previews it
+//! fetches, so the client can be worked on and tried out without a server.
+//! Built with `--features mock-server`.
+//!
+//! State lives in local storage, or in memory in tests. None of this is
+//! meant to be secure.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use gloo::storage::{LocalStorage, Storage};
+use gloo::timers::future::TimeoutFuture;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use wasm_bindgen_futures::spawn_local;
+use yew_agent::Dispatched;
+
+use crate::components::{links, markdown, previews};
+use crate::protocol::{ChatMessage, Conversation, ErrorCode, LinkPreview, ServerFrame};
+use crate::services::event_bus::{Event, EventBus, Request};
+
+const ACCOUNTS_KEY: &str = "yewchat.mock.accounts";
+const REFRESH_TOKENS_KEY: &str = "yewchat.mock.refresh";
+/// Short on purpose, so that refreshing gets exercised.
+const TOKEN_LIFETIME_MS: f64 = 5.0 * 60_000.0;
+const MAX_NAME_LEN: usize = 32;
+const MIN_PASSWORD_LEN: usize = 8;
+/// How long the stub unfurler takes, so previews arrive after the message
+/// as they do from the server.
+const UNFURL_DELAY_MS: u32 = 800;
+/// Previews made up per message, like the server's limit.
+const MAX_PREVIEWS: usize = 3;
+
+/// Where the endpoints keep their state and get the time and randomness
+/// from.
+trait Host {
+    /// The value saved under `key`, or the default if there is none.
+    fn load<T: DeserializeOwned + Default>(&self, key: &str) -> T;
+    fn save<T: Serialize>(&mut self, key: &str, value: &T);
+    /// Milliseconds since the epoch, like `Date.now()`.
+    fn now(&self) -> f64;
+    /// In `[0, 1)`, like `Math.random()`.
+    fn random(&mut self) -> f64;
+}
+
+/// Local storage and the browser's clock.
+struct Browser;
+
+impl Host for Browser {
+    fn load<T: DeserializeOwned + Default>(&self, key: &str) -> T {
+        LocalStorage::get(key).unwrap_or_default()
+    }
+
+    fn save<T: Serialize>(&mut self, key: &str, value: &T) {
+        if let Err(e) = LocalStorage::set(key, value) {
+            log::error!("mock server: failed to save {}: {:?}", key, e);
+        }
+    }
+
+    fn now(&self) -> f64 {
+        js_sys::Date::now()
+    }
+
+    fn random(&mut self) -> f64 {
+        js_sys::Math::random()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Account {
+    salt: String,
+    hash: u64,
+}
+
+#[derive(Deserialize)]
+struct Credentials {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct RefreshRequest {
+    #[serde(rename = "refreshToken")]
+    refresh_token: String,
+}
+
+/// Handles a POST to `/auth/<path>` like the real server, returning the
+/// status and the JSON body.
+pub fn auth(path: &str, body: &str) -> (u16, String) {
+    handle_auth(&mut Browser, path, body)
+}
+
+fn handle_auth(host: &mut impl Host, path: &str, body: &str) -> (u16, String) {
+    let result = match path {
+        "signup" => serde_json::from_str(body)
+            .map_err(bad_request)
+            .and_then(|credentials| sign_up(host, credentials)),
+        "signin" => serde_json::from_str(body)
+            .map_err(bad_request)
+            .and_then(|credentials| sign_in(host, credentials)),
+        "refresh" => serde_json::from_str(body)
+            .map_err(bad_request)
+            .and_then(|request| refresh(host, request)),
+        _ => Err((404, error("No such endpoint", ErrorCode::Other))),
+    };
+    match result {
+        Ok(token) => (200, token),
+        Err(answer) => answer,
+    }
+}
+
+/// Publishes the `unfurl` frame the server would send a little after
+/// `frame`, when that is a message with links to preview. `me` is who we are
+/// signed in as, which tells the conversation of a direct message.
+pub fn unfurl(frame: &ServerFrame, me: &str) {
+    let (ServerFrame::Message { message } | ServerFrame::Direct { message }) = frame else {
+        return;
+    };
+    let Some(unfurl) = stub_unfurl(&message.conversation(me), message) else {
+        return;
+    };
+    spawn_local(async move {
+        TimeoutFuture::new(UNFURL_DELAY_MS).await;
+        EventBus::dispatcher().send(Request::Publish(Event::Server(unfurl)));
+    });
+}
+
+/// The `unfurl` frame for `message`, made up from the URLs alone: the host
+/// as the title, the path as the description. Links to images and videos
+/// are embedded, not previewed. `None` once the message has previews, or
+/// has nothing to preview.
+fn stub_unfurl(conversation: &Conversation, message: &ChatMessage) -> Option<ServerFrame> {
+    let message_id = message.id.clone()?;
+    if message.deleted || !message.previews.is_empty() {
+        return None;
+    }
+    let document = markdown::parse(&message.message);
+    let mut previews: Vec<LinkPreview> = vec![];
+    for url in document.links() {
+        if previews.len() == MAX_PREVIEWS {
+            break;
+        }
+        if links::is_web_url(url) && links::embed_kind(url).is_none() && !previews.iter().any(|p| p.url == url) {
+            previews.push(stub_preview(url));
+        }
+    }
+    if previews.is_empty() {
+        return None;
+    }
+
+    Some(ServerFrame::Unfurl {
+        conversation: conversation.clone(),
+        message_id,
+        previews,
+    })
+}
+
+fn stub_preview(url: &str) -> LinkPreview {
+    let host = previews::host(url);
+    let path = url
+        .split_once("://")
+        .and_then(|(_, rest)| rest.split_once('/'))
+        .map(|(_, path)| path)
+        .filter(|path| !path.is_empty());
+    LinkPreview {
+        url: url.to_owned(),
+        title: Some(host.to_owned()),
+        description: Some(match path {
+            Some(path) => format!("/{}", path),
+            None => "Preview made up by the mock server.".to_owned(),
+        }),
+        image: Some(format!("https://avatars.dicebear.com/api/identicon/{}.svg", host)),
+    }
+}
+
+fn sign_up(host: &mut impl Host, credentials: Credentials) -> Result<String, (u16, String)> {
+    let name = &credentials.username;
+    if name.is_empty()
+        || name.len() > MAX_NAME_LEN
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err((
+            422,
+            error("Names may only use letters, digits, '-' and '_'", ErrorCode::InvalidName),
+        ));
+    }
+    if credentials.password.chars().count() < MIN_PASSWORD_LEN {
+        return Err((
+            422,
+            error(
+                &format!("Passwords need at least {} characters", MIN_PASSWORD_LEN),
+                ErrorCode::Other,
+            ),
+        ));
+    }
+
+    let mut accounts: HashMap<String, Account> = host.load(ACCOUNTS_KEY);
+    if accounts.contains_key(name) {
+        return Err((409, error("That name is already taken", ErrorCode::NameTaken)));
+    }
+    let salt = random_string(host);
+    let hash = hash_password(&salt, &credentials.password);
+    accounts.insert(name.clone(), Account { salt, hash });
+    host.save(ACCOUNTS_KEY, &accounts);
+
+    Ok(issue_token(host, name))
+}
+
+fn sign_in(host: &mut impl Host, credentials: Credentials) -> Result<String, (u16, String)> {
+    let accounts: HashMap<String, Account> = host.load(ACCOUNTS_KEY);
+    match accounts.get(&credentials.username) {
+        Some(account) if account.hash == hash_password(&account.salt, &credentials.password) => {
+            Ok(issue_token(host, &credentials.username))
+        }
+        _ => Err((
+            401,
+            error("Wrong username or password", ErrorCode::InvalidCredentials),
+        )),
+    }
+}
+
+fn refresh(host: &mut impl Host, request: RefreshRequest) -> Result<String, (u16, String)> {
+    // Refresh tokens are single use.
+    let mut refresh_tokens: HashMap<String, String> = host.load(REFRESH_TOKENS_KEY);
+    match refresh_tokens.remove(&request.refresh_token) {
+        Some(username) => {
+            host.save(REFRESH_TOKENS_KEY, &refresh_tokens);
+            Ok(issue_token(host, &username))
+        }
+        None => Err((401, error("Session expired", ErrorCode::Unauthorized))),
+    }
+}
+
+fn issue_token(host: &mut impl Host, username: &str) -> String {
+    let refresh = random_string(host);
+    let mut refresh_tokens: HashMap<String, String> = host.load(REFRESH_TOKENS_KEY);
+    refresh_tokens.insert(refresh.clone(), username.to_owned());
+    host.save(REFRESH_TOKENS_KEY, &refresh_tokens);
+
+    json!({
+        "token": random_string(host),
+        "refreshToken": refresh,
+        "expiresAt": host.now() + TOKEN_LIFETIME_MS,
+    })
+    .to_string()
+}
+
+fn hash_password(salt: &str, password: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (salt, password).hash(&mut hasher);
+    hasher.finish()
+}
+
+fn random_string(host: &mut impl Host) -> String {
+    format!("{:016x}", (host.random() * u64::MAX as f64) as u64)
+}
+
+fn bad_request(e: serde_json::Error) -> (u16, String) {
+    (400, error(&e.to_string(), ErrorCode::Other))
+}
+
+fn error(reason: &str, code: ErrorCode) -> String {
+    json!({ "error": reason, "code": code }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: f64 = 1_700_000_000_000.0;
+
+    /// Storage in a map, a stopped clock and a counter for randomness.
+    #[derive(Default)]
+    struct Memory {
+        items: HashMap<String, String>,
+        draws: u32,
+    }
+
+    impl Host for Memory {
+        fn load<T: DeserializeOwned + Default>(&self, key: &str) -> T {
+            self.items
+                .get(key)
+                .and_then(|json| serde_json::from_str(json).ok())
+                .unwrap_or_default()
+        }
+
+        fn save<T: Serialize>(&mut self, key: &str, value: &T) {
+            self.items.insert(key.to_owned(), serde_json::to_string(value).unwrap());
+        }
+
+        fn now(&self) -> f64 {
+            NOW
+        }
+
+        fn random(&mut self) -> f64 {
+            self.draws += 1;
+            f64::from(self.draws) / 1000.0
+        }
+    }
+
+    fn credentials(username: &str, password: &str) -> String {
+        json!({ "username": username, "password": password }).to_string()
+    }
+
+    fn refresh_request(refresh_token: &str) -> String {
+        json!({ "refreshToken": refresh_token }).to_string()
+    }
+
+    /// The token of a 200 answer.
+    fn token(answer: (u16, String)) -> serde_json::Value {
+        assert_eq!(answer.0, 200, "{}", answer.1);
+        serde_json::from_str(&answer.1).unwrap()
+    }
+
+    /// The status and code of an error answer.
+    fn rejection(answer: (u16, String)) -> (u16, ErrorCode) {
+        let body: serde_json::Value = serde_json::from_str(&answer.1).unwrap();
+        (answer.0, serde_json::from_value(body["code"].clone()).unwrap())
+    }
+
+    #[test]
+    fn sign_up_issues_a_token() {
+        let mut host = Memory::default();
+        let token = token(handle_auth(&mut host, "signup", &credentials("alice", "correct horse")));
+
+        assert!(token["token"].is_string());
+        assert!(token["refreshToken"].is_string());
+        assert_ne!(token["token"], token["refreshToken"]);
+        assert_eq!(token["expiresAt"], NOW + TOKEN_LIFETIME_MS);
+    }
+
+    #[test]
+    fn sign_up_checks_the_name_and_password() {
+        let mut host = Memory::default();
+        let answer = handle_auth(&mut host, "signup", &credentials("al ice", "correct horse"));
+        assert_eq!(rejection(answer), (422, ErrorCode::InvalidName));
+        let answer = handle_auth(&mut host, "signup", &credentials("alice", "short"));
+        assert_eq!(rejection(answer), (422, ErrorCode::Other));
+    }
+
+    #[test]
+    fn names_are_taken_once() {
+        let mut host = Memory::default();
+        token(handle_auth(&mut host, "signup", &credentials("alice", "correct horse")));
+        let answer = handle_auth(&mut host, "signup", &credentials("alice", "battery staple"));
+        assert_eq!(rejection(answer), (409, ErrorCode::NameTaken));
+    }
+
+    #[test]
+    fn sign_in_checks_the_password() {
+        let mut host = Memory::default();
+        token(handle_auth(&mut host, "signup", &credentials("alice", "correct horse")));
+
+        token(handle_auth(&mut host, "signin", &credentials("alice", "correct horse")));
+        let answer = handle_auth(&mut host, "signin", &credentials("alice", "battery staple"));
+        assert_eq!(rejection(answer), (401, ErrorCode::InvalidCredentials));
+        let answer = handle_auth(&mut host, "signin", &credentials("bob", "correct horse"));
+        assert_eq!(rejection(answer), (401, ErrorCode::InvalidCredentials));
+    }
+
+    #[test]
+    fn refresh_trades_the_token_for_a_new_one() {
+        let mut host = Memory::default();
+        let first = token(handle_auth(&mut host, "signup", &credentials("alice", "correct horse")));
+        let refresh = first["refreshToken"].as_str().unwrap();
+
+        let second = token(handle_auth(&mut host, "refresh", &refresh_request(refresh)));
+        assert_ne!(second["token"], first["token"]);
+        assert_ne!(second["refreshToken"], first["refreshToken"]);
+
+        let refresh = second["refreshToken"].as_str().unwrap();
+        token(handle_auth(&mut host, "refresh", &refresh_request(refresh)));
+    }
+
+    #[test]
+    fn refresh_tokens_are_single_use() {
+        let mut host = Memory::default();
+        let first = token(handle_auth(&mut host, "signup", &credentials("alice", "correct horse")));
+        let refresh = first["refreshToken"].as_str().unwrap();
+
+        token(handle_auth(&mut host, "refresh", &refresh_request(refresh)));
+        let answer = handle_auth(&mut host, "refresh", &refresh_request(refresh));
+        assert_eq!(rejection(answer), (401, ErrorCode::Unauthorized));
+    }
+
+    #[test]
+    fn unknown_refresh_tokens_are_rejected() {
+        let mut host = Memory::default();
+        let answer = handle_auth(&mut host, "refresh", &refresh_request("made up"));
+        assert_eq!(rejection(answer), (401, ErrorCode::Unauthorized));
+    }
+
+    fn message(text: &str) -> ChatMessage {
+        serde_json::from_value(json!({ "id": "m1", "from": "alice", "message": text })).unwrap()
+    }
+
+    fn previewed(text: &str) -> Vec<String> {
+        match stub_unfurl(&Conversation::default_room(), &message(text)) {
+            Some(ServerFrame::Unfurl { previews, .. }) => previews.into_iter().map(|p| p.url).collect(),
+            Some(frame) => panic!("not an unfurl: {:?}", frame),
+            None => vec![],
+        }
+    }
+
+    #[test]
+    fn links_are_previewed_once() {
+        assert_eq!(
+            previewed("see https://example.com/a and [this](https://example.com/a) or https://rust-lang.org"),
+            ["https://example.com/a", "https://rust-lang.org"]
+        );
+    }
+
+    #[test]
+    fn images_code_and_plain_text_are_not_previewed() {
+        assert!(previewed("https://example.com/cat.png").is_empty());
+        assert!(previewed("`https://example.com`").is_empty());
+        assert!(previewed("no links here").is_empty());
+    }
+
+    #[test]
+    fn previews_are_limited() {
+        let text = (0..5).map(|i| format!("https://example.com/{}", i)).collect::<Vec<_>>().join(" ");
+        assert_eq!(previewed(&text).len(), MAX_PREVIEWS);
+    }
+
+    #[test]
+    fn malformed_requests_are_bad_requests() {
+        let mut host = Memory::default();
+        assert_eq!(handle_auth(&mut host, "signin", "{").0, 400);
+        assert_eq!(handle_auth(&mut host, "signout", "{}").0, 404);
+    }
+}
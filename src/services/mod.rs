@@ -1,2 +1,11 @@
+pub mod activity;
+pub mod auth;
+pub mod config;
+#[cfg(feature = "mock-server")]
+pub mod mock_server;
+pub mod outbox;
+pub mod session;
+pub mod storage;
+pub mod uploads;
 pub mod websocket;
 pub mod event_bus;
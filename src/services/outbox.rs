@@ -0,0 +1,161 @@
+use gloo::storage::{LocalStorage, Storage};
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{Attachment, Conversation};
+
+const STORAGE_PREFIX: &str = "yewchat.outbox.";
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DeliveryStatus {
+    /// Waiting for a connection.
+    Pending,
+    /// Written to the socket, waiting for the server to acknowledge it.
+    Sent,
+    /// The connection dropped before the server acknowledged it.
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OutgoingMessage {
+    pub client_id: String,
+    #[serde(default = "Conversation::default_room")]
+    pub conversation: Conversation,
+    pub text: String,
+    /// Id of the message this one answers, if it is a reply.
+    #[serde(default)]
+    pub reply_to: Option<String>,
+    /// Goes to the thread of `reply_to` only.
+    #[serde(default)]
+    pub in_thread: bool,
+    /// Uploaded files that go with the text.
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    pub status: DeliveryStatus,
+}
+
+/// Messages the server has not acknowledged yet. The queue is kept in
+/// local storage so nothing typed is lost across reloads.
+pub struct Outbox {
+    key: String,
+    entries: Vec<OutgoingMessage>,
+}
+
+impl Outbox {
+    pub fn load(username: &str) -> Self {
+        let key = format!("{}{}", STORAGE_PREFIX, username);
+        let mut entries: Vec<OutgoingMessage> = LocalStorage::get(&key).unwrap_or_default();
+
+        // Whatever was in flight when the page went away never got an ack.
+        for entry in entries.iter_mut() {
+            if entry.status == DeliveryStatus::Sent {
+                entry.status = DeliveryStatus::Failed;
+            }
+        }
+
+        Self { key, entries }
+    }
+
+    pub fn entries(&self) -> &[OutgoingMessage] {
+        &self.entries
+    }
+
+    pub fn push(
+        &mut self,
+        conversation: &Conversation,
+        text: String,
+        reply_to: Option<String>,
+        in_thread: bool,
+        attachments: Vec<Attachment>,
+    ) -> &OutgoingMessage {
+        self.entries.push(OutgoingMessage {
+            client_id: new_client_id(),
+            conversation: conversation.clone(),
+            text,
+            reply_to,
+            in_thread,
+            attachments,
+            status: DeliveryStatus::Pending,
+        });
+        self.save();
+        self.entries.last().unwrap()
+    }
+
+    pub fn set_status(&mut self, client_id: &str, status: DeliveryStatus) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.client_id == client_id) {
+            entry.status = status;
+            self.save();
+        }
+    }
+
+    /// Marks everything that was in flight as failed, e.g. after the
+    /// connection dropped.
+    pub fn fail_in_flight(&mut self) -> bool {
+        let mut changed = false;
+        for entry in self.entries.iter_mut() {
+            if entry.status == DeliveryStatus::Sent {
+                entry.status = DeliveryStatus::Failed;
+                changed = true;
+            }
+        }
+        if changed {
+            self.save();
+        }
+        changed
+    }
+
+    /// Drops the entry the server acknowledged. Returns whether it was ours.
+    pub fn acknowledge(&mut self, client_id: &str) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|e| e.client_id != client_id);
+        let found = self.entries.len() != before;
+        if found {
+            self.save();
+        }
+        found
+    }
+
+    /// Servers that do not echo client ids can only be matched on content,
+    /// so acknowledge the oldest in-flight entry with the same text.
+    pub fn acknowledge_text(&mut self, conversation: &Conversation, text: &str) -> bool {
+        let position = self
+            .entries
+            .iter()
+            .position(|e| e.status != DeliveryStatus::Pending && &e.conversation == conversation && e.text == text);
+        match position {
+            Some(index) => {
+                self.entries.remove(index);
+                self.save();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forgets everything, e.g. on logout.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.save();
+    }
+
+    fn save(&self) {
+        let result = if self.entries.is_empty() {
+            LocalStorage::delete(&self.key);
+            Ok(())
+        } else {
+            LocalStorage::set(&self.key, &self.entries)
+        };
+        if let Err(e) = result {
+            log::error!("Failed to persist outbox: {:?}", e);
+        }
+    }
+}
+
+/// A random id for something we send, unique enough to match the server's
+/// answer to it.
+pub fn new_client_id() -> String {
+    format!(
+        "{:x}-{:08x}",
+        js_sys::Date::now() as u64,
+        (js_sys::Math::random() * f64::from(u32::MAX)) as u32
+    )
+}
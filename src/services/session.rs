@@ -0,0 +1,58 @@
+use gloo::storage::{LocalStorage, Storage};
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{Conversation, Presence};
+use crate::services::auth::Token;
+
+const STORAGE_KEY: &str = "yewchat.session";
+
+/// Who is logged in, kept in local storage so a reload does not log the
+/// user out. Sessions stored before accounts existed have no token and
+/// fail to load, which sends those users back to the login screen.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub username: String,
+    /// Server chosen on the login screen, overriding the configured one.
+    #[serde(default)]
+    pub server: Option<String>,
+    pub token: Token,
+    #[serde(default)]
+    pub preferences: Preferences,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Preferences {
+    /// Conversation to reopen when landing on `/chat`.
+    pub last_conversation: Option<Conversation>,
+    /// Status picked in the sidebar, online when unset.
+    #[serde(default)]
+    pub presence: Option<Presence>,
+    /// Code blocks with more lines than this start collapsed.
+    #[serde(default)]
+    pub collapse_code_after: Option<usize>,
+}
+
+impl Session {
+    pub fn new(username: String, server: Option<String>, token: Token) -> Self {
+        Self {
+            username,
+            server,
+            token,
+            preferences: Preferences::default(),
+        }
+    }
+
+    pub fn load() -> Option<Self> {
+        LocalStorage::get(STORAGE_KEY).ok()
+    }
+
+    pub fn save(&self) {
+        if let Err(e) = LocalStorage::set(STORAGE_KEY, self) {
+            log::error!("Failed to persist session: {:?}", e);
+        }
+    }
+
+    pub fn clear() {
+        LocalStorage::delete(STORAGE_KEY);
+    }
+}
@@ -0,0 +1,157 @@
+use gloo::storage::{errors::StorageError, LocalStorage, Storage};
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{ChatMessage, Conversation};
+
+/// Bump when `CachedConversation` changes shape. Entries written with
+/// another version are discarded instead of being misread.
+const SCHEMA_VERSION: u32 = 1;
+const STORAGE_PREFIX: &str = "yewchat.cache.";
+/// Only the tail of each conversation is kept, the rest comes from history.
+const MAX_CACHED_MESSAGES: usize = 200;
+/// How many conversations may be evicted to make room for a single save.
+const MAX_EVICTIONS: usize = 8;
+
+#[derive(Serialize, Deserialize)]
+struct CachedConversation {
+    version: u32,
+    /// `Date.now()` of the last save, used to evict the stalest entry first.
+    saved_at: f64,
+    messages: Vec<ChatMessage>,
+}
+
+/// Recent messages per conversation, kept in local storage so the chat can
+/// show something before the socket connects or while offline.
+pub struct MessageCache {
+    prefix: String,
+}
+
+impl MessageCache {
+    pub fn new(username: &str) -> Self {
+        Self {
+            prefix: format!("{}{}.", STORAGE_PREFIX, escape(username)),
+        }
+    }
+
+    pub fn load(&self, conversation: &Conversation) -> Vec<ChatMessage> {
+        let key = self.key(conversation);
+        match LocalStorage::get::<CachedConversation>(&key) {
+            Ok(cached) if cached.version == SCHEMA_VERSION => cached.messages,
+            Ok(_) => {
+                log::debug!("dropping cache {} with an old schema", key);
+                LocalStorage::delete(&key);
+                vec![]
+            }
+            Err(_) => vec![],
+        }
+    }
+
+    pub fn save(&self, conversation: &Conversation, messages: &[ChatMessage]) {
+        let key = self.key(conversation);
+        let tail = &messages[messages.len().saturating_sub(MAX_CACHED_MESSAGES)..];
+        let entry = CachedConversation {
+            version: SCHEMA_VERSION,
+            saved_at: js_sys::Date::now(),
+            messages: tail.to_vec(),
+        };
+
+        for _ in 0..=MAX_EVICTIONS {
+            match LocalStorage::set(&key, &entry) {
+                Ok(()) => return,
+                // Most likely over quota, make room and try again.
+                Err(StorageError::JsError(e)) => {
+                    log::warn!("Failed to cache {}: {}", key, e);
+                    if !self.evict_stalest(&key) {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    log::error!("Failed to cache {}: {:?}", key, e);
+                    return;
+                }
+            }
+        }
+        log::error!("Giving up caching {}", key);
+    }
+
+    /// Forgets every cached conversation of this user, e.g. on logout.
+    pub fn clear(&self) {
+        for key in self.keys() {
+            LocalStorage::delete(&key);
+        }
+    }
+
+    /// Removes the least recently saved conversation other than `keep`.
+    /// Returns whether anything was removed.
+    fn evict_stalest(&self, keep: &str) -> bool {
+        let stalest = self
+            .keys()
+            .into_iter()
+            .filter(|key| key != keep)
+            .map(|key| {
+                let saved_at = LocalStorage::get::<CachedConversation>(&key)
+                    .map(|cached| cached.saved_at)
+                    .unwrap_or(0.0);
+                (key, saved_at)
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        match stalest {
+            Some((key, _)) => {
+                log::debug!("evicting cache {}", key);
+                LocalStorage::delete(&key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Every storage key belonging to this cache.
+    fn keys(&self) -> Vec<String> {
+        let storage = LocalStorage::raw();
+        let length = storage.length().unwrap_or(0);
+        (0..length)
+            .filter_map(|index| storage.key(index).ok().flatten())
+            .filter(|key| key.starts_with(&self.prefix))
+            .collect()
+    }
+
+    fn key(&self, conversation: &Conversation) -> String {
+        match conversation {
+            Conversation::Room(room) => format!("{}room.{}", self.prefix, room),
+            Conversation::Direct(peer) => format!("{}direct.{}", self.prefix, peer),
+        }
+    }
+}
+
+/// Percent-encodes the `.` ending the username in keys, and `%` itself, so
+/// that user `a` does not mistake the keys of user `a.b` for its own.
+fn escape(username: &str) -> String {
+    username.replace('%', "%25").replace('.', "%2E")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_usernames_keep_their_keys() {
+        let cache = MessageCache::new("alice");
+        assert_eq!(
+            cache.key(&Conversation::Room("general".to_owned())),
+            "yewchat.cache.alice.room.general"
+        );
+    }
+
+    #[test]
+    fn users_do_not_share_keys() {
+        let users = ["a", "a.b", "a%2Eb", "a.room", "a%"];
+        for user in users {
+            let cache = MessageCache::new(user);
+            for other in users.iter().filter(|other| **other != user) {
+                let key = MessageCache::new(other).key(&Conversation::Direct("c".to_owned()));
+                assert!(!key.starts_with(&cache.prefix), "{} would own {}", user, key);
+            }
+        }
+    }
+}
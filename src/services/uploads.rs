@@ -0,0 +1,318 @@
+use std::fmt;
+use std::future::Future;
+
+use gloo::file::futures::read_as_bytes;
+use gloo::file::{Blob, File};
+
+use crate::protocol::{Attachment, ClientFrame, Conversation, UploadChunk};
+use crate::services::outbox::new_client_id;
+
+/// Files larger than this are refused before anything is sent.
+pub const MAX_FILE_SIZE: u64 = 25 * 1024 * 1024;
+/// No message carries more files than this.
+pub const MAX_ATTACHMENTS: usize = 10;
+/// Bytes per binary frame.
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Types of file that may be shared. Anything else, executables above all,
+/// is refused.
+const ALLOWED_TYPES: [&str; 15] = [
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "video/mp4",
+    "video/webm",
+    "audio/mpeg",
+    "audio/ogg",
+    "application/pdf",
+    "application/zip",
+    "application/json",
+    "text/plain",
+    "text/csv",
+    "text/markdown",
+    "text/x-log",
+];
+
+/// For files the browser could not tell the type of.
+const TYPES_BY_EXTENSION: [(&str, &str); 6] = [
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("log", "text/x-log"),
+    ("txt", "text/plain"),
+    ("json", "application/json"),
+    ("zip", "application/zip"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum UploadStatus {
+    Uploading,
+    /// Ready to go with the next message.
+    Done(Attachment),
+    Failed(String),
+}
+
+/// A file picked, dropped or pasted into the composer.
+pub struct Upload {
+    pub id: String,
+    pub conversation: Conversation,
+    pub name: String,
+    pub content_type: String,
+    pub size: u64,
+    /// Bytes the server confirmed having.
+    pub received: u64,
+    pub status: UploadStatus,
+    file: File,
+}
+
+impl Upload {
+    fn announce(&self) -> ClientFrame {
+        ClientFrame::Upload {
+            conversation: self.conversation.clone(),
+            upload_id: self.id.clone(),
+            name: self.name.clone(),
+            content_type: self.content_type.clone(),
+            size: self.size,
+        }
+    }
+}
+
+/// Why a file was not taken.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Rejection {
+    TooLarge { name: String },
+    UnsupportedType { name: String },
+    TooMany,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::TooLarge { name } => {
+                write!(f, "{} is larger than {} MB", name, MAX_FILE_SIZE / (1024 * 1024))
+            }
+            Rejection::UnsupportedType { name } => write!(f, "{} is not a type of file that can be shared", name),
+            Rejection::TooMany => write!(f, "At most {} files go with one message", MAX_ATTACHMENTS),
+        }
+    }
+}
+
+/// Uploads of the composer, in every conversation. Each streams to the
+/// server as soon as it is added, one chunk per `uploadprogress`, and goes
+/// out with the next message once done.
+#[derive(Default)]
+pub struct Uploads {
+    entries: Vec<Upload>,
+}
+
+impl Uploads {
+    /// Checks `file` against the limits and starts tracking it. Returns
+    /// the frame announcing it to the server.
+    pub fn add(&mut self, conversation: &Conversation, file: File) -> Result<ClientFrame, Rejection> {
+        let name = file.name();
+        let attached = self.of(conversation).count();
+        let content_type = check(&name, file.size(), &file.raw_mime_type(), attached)?;
+
+        let upload = Upload {
+            id: new_client_id(),
+            conversation: conversation.clone(),
+            name,
+            content_type,
+            size: file.size(),
+            received: 0,
+            status: UploadStatus::Uploading,
+            file,
+        };
+        let frame = upload.announce();
+        self.entries.push(upload);
+        Ok(frame)
+    }
+
+    /// The uploads of `conversation`, in the order they were added.
+    pub fn of<'a>(&'a self, conversation: &'a Conversation) -> impl Iterator<Item = &'a Upload> + 'a {
+        self.entries.iter().filter(move |u| &u.conversation == conversation)
+    }
+
+    pub fn is_uploading(&self, id: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|u| u.id == id && u.status == UploadStatus::Uploading)
+    }
+
+    /// Frames announcing every unfinished upload again, after a reconnect.
+    /// The server answers each with where to carry on.
+    pub fn resume(&self) -> Vec<ClientFrame> {
+        self.entries
+            .iter()
+            .filter(|u| u.status == UploadStatus::Uploading)
+            .map(Upload::announce)
+            .collect()
+    }
+
+    /// The server has `received` bytes of upload `id`. Returns the read of
+    /// the chunk it wants next, unless it has everything.
+    pub fn progress(&mut self, id: &str, received: u64) -> Option<impl Future<Output = Result<UploadChunk, String>>> {
+        let upload = self
+            .entries
+            .iter_mut()
+            .find(|u| u.id == id && u.status == UploadStatus::Uploading)?;
+        upload.received = received.min(upload.size);
+        if upload.received == upload.size {
+            return None;
+        }
+
+        let offset = upload.received;
+        let blob = Blob::slice(&upload.file, offset, (offset + CHUNK_SIZE).min(upload.size));
+        let upload_id = upload.id.clone();
+        Some(async move {
+            let data = read_as_bytes(&blob).await.map_err(|e| e.to_string())?;
+            Ok(UploadChunk { upload_id, offset, data })
+        })
+    }
+
+    pub fn finish(&mut self, id: &str, attachment: Attachment) -> bool {
+        self.set_status(id, UploadStatus::Done(attachment))
+    }
+
+    pub fn fail(&mut self, id: &str, reason: String) -> bool {
+        self.set_status(id, UploadStatus::Failed(reason))
+    }
+
+    /// Forgets upload `id`. Returns the frame telling the server to stop,
+    /// when it was still going.
+    pub fn remove(&mut self, id: &str) -> Option<ClientFrame> {
+        let index = self.entries.iter().position(|u| u.id == id)?;
+        let upload = self.entries.remove(index);
+        (upload.status == UploadStatus::Uploading).then_some(ClientFrame::CancelUpload { upload_id: upload.id })
+    }
+
+    /// Whether any upload of `conversation` is not finished yet.
+    pub fn pending(&self, conversation: &Conversation) -> bool {
+        self.of(conversation).any(|u| u.status == UploadStatus::Uploading)
+    }
+
+    /// Hands over the finished uploads of `conversation`, for the message
+    /// about to be sent. Failed ones stay until removed.
+    pub fn take_done(&mut self, conversation: &Conversation) -> Vec<Attachment> {
+        let mut done = vec![];
+        self.entries.retain(|u| match &u.status {
+            UploadStatus::Done(attachment) if &u.conversation == conversation => {
+                done.push(attachment.clone());
+                false
+            }
+            _ => true,
+        });
+        done
+    }
+
+    fn set_status(&mut self, id: &str, status: UploadStatus) -> bool {
+        match self.entries.iter_mut().find(|u| u.id == id) {
+            Some(upload) if upload.status == UploadStatus::Uploading => {
+                upload.status = status;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Checks a file of `size` bytes and type `mime_type`, as the browser
+/// reported it, against the limits, with `attached` files already going
+/// with the message. Returns the type to upload it as.
+fn check(name: &str, size: u64, mime_type: &str, attached: usize) -> Result<String, Rejection> {
+    if attached >= MAX_ATTACHMENTS {
+        return Err(Rejection::TooMany);
+    }
+    if size > MAX_FILE_SIZE {
+        return Err(Rejection::TooLarge { name: name.to_owned() });
+    }
+    let content_type = match mime_type {
+        "" => guess_type(name).unwrap_or_default(),
+        known => known,
+    };
+    if !ALLOWED_TYPES.contains(&content_type) {
+        return Err(Rejection::UnsupportedType { name: name.to_owned() });
+    }
+    Ok(content_type.to_owned())
+}
+
+fn guess_type(name: &str) -> Option<&'static str> {
+    let (_, extension) = name.rsplit_once('.')?;
+    let extension = extension.to_ascii_lowercase();
+    TYPES_BY_EXTENSION
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, content_type)| *content_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_limit_is_inclusive() {
+        assert_eq!(check("a.png", MAX_FILE_SIZE, "image/png", 0), Ok("image/png".to_owned()));
+        assert_eq!(
+            check("a.png", MAX_FILE_SIZE + 1, "image/png", 0),
+            Err(Rejection::TooLarge { name: "a.png".to_owned() })
+        );
+        assert!(check("empty.txt", 0, "text/plain", 0).is_ok());
+    }
+
+    #[test]
+    fn only_allowed_types_go() {
+        for (name, mime_type) in [("a.pdf", "application/pdf"), ("a.mp4", "video/mp4"), ("a.csv", "text/csv")] {
+            assert_eq!(check(name, 1, mime_type, 0), Ok(mime_type.to_owned()));
+        }
+        for (name, mime_type) in [
+            ("setup.exe", "application/x-msdownload"),
+            ("page.html", "text/html"),
+            ("logo.svg", "image/svg+xml"),
+            ("run.sh", "application/x-sh"),
+        ] {
+            assert_eq!(
+                check(name, 1, mime_type, 0),
+                Err(Rejection::UnsupportedType { name: name.to_owned() })
+            );
+        }
+    }
+
+    #[test]
+    fn count_limit() {
+        assert!(check("a.png", 1, "image/png", MAX_ATTACHMENTS - 1).is_ok());
+        assert_eq!(check("a.png", 1, "image/png", MAX_ATTACHMENTS), Err(Rejection::TooMany));
+    }
+
+    #[test]
+    fn limits_are_checked_before_the_type() {
+        assert_eq!(check("a.exe", 1, "application/x-msdownload", MAX_ATTACHMENTS), Err(Rejection::TooMany));
+        assert_eq!(
+            check("a.exe", MAX_FILE_SIZE + 1, "application/x-msdownload", 0),
+            Err(Rejection::TooLarge { name: "a.exe".to_owned() })
+        );
+    }
+
+    #[test]
+    fn missing_types_are_guessed_from_the_name() {
+        assert_eq!(check("notes.md", 1, "", 0), Ok("text/markdown".to_owned()));
+        assert_eq!(check("SERVER.LOG", 1, "", 0), Ok("text/x-log".to_owned()));
+        assert_eq!(check("archive.tar.Zip", 1, "", 0), Ok("application/zip".to_owned()));
+        assert_eq!(
+            check("Makefile", 1, "", 0),
+            Err(Rejection::UnsupportedType { name: "Makefile".to_owned() })
+        );
+        assert_eq!(
+            check("tool.exe", 1, "", 0),
+            Err(Rejection::UnsupportedType { name: "tool.exe".to_owned() })
+        );
+    }
+
+    #[test]
+    fn guessing_types() {
+        assert_eq!(guess_type("data.JSON"), Some("application/json"));
+        assert_eq!(guess_type("README"), None);
+        assert_eq!(guess_type("trailing."), None);
+        assert_eq!(guess_type(".txt"), Some("text/plain"));
+        assert_eq!(guess_type("photo.png"), None);
+    }
+}
@@ -1,53 +1,358 @@
-use futures::{channel::mpsc::Sender, SinkExt, StreamExt};
-use reqwasm::websocket::{futures::WebSocket, Message};
+use futures::{
+    channel::{
+        mpsc::{Receiver, Sender},
+        oneshot,
+    },
+    future::{self, Either},
+    stream, SinkExt, StreamExt,
+};
+use gloo::timers::future::TimeoutFuture;
+use reqwasm::websocket::{futures::WebSocket, Message, WebSocketError};
 
 use wasm_bindgen_futures::spawn_local;
-use yew_agent::Dispatched;
+use yew_agent::{Dispatched, Dispatcher};
 
-use crate::services::event_bus::{EventBus, Request};
+use crate::protocol::{ClientFrame, ErrorCode, ServerFrame, UploadChunk};
+use crate::services::auth::{self, AuthError, Token};
+use crate::services::config;
+use crate::services::event_bus::{Event, EventBus, Request, UiEvent};
+#[cfg(feature = "mock-server")]
+use crate::services::mock_server;
 
+/// Delay before the first reconnection attempt, doubled on every failure.
+const BACKOFF_BASE_MS: u32 = 500;
+/// Upper bound for the reconnection delay.
+const BACKOFF_MAX_MS: u32 = 30_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Reconnecting { attempt: u32 },
+    Disconnected,
+}
+
+/// What goes out on the socket: frames as JSON text, pieces of uploads as
+/// binary.
+#[derive(Debug)]
+pub enum Outgoing {
+    Frame(ClientFrame),
+    Chunk(UploadChunk),
+}
+
+impl From<ClientFrame> for Outgoing {
+    fn from(frame: ClientFrame) -> Self {
+        Outgoing::Frame(frame)
+    }
+}
+
+impl From<UploadChunk> for Outgoing {
+    fn from(chunk: UploadChunk) -> Self {
+        Outgoing::Chunk(chunk)
+    }
+}
+
+#[derive(Debug)]
 pub struct WebsocketService {
-    pub tx: Sender<String>,
+    pub tx: Sender<Outgoing>,
+    // Dropping the service drops this, which stops the supervisor even while
+    // it is waiting to reconnect.
+    _alive: oneshot::Receiver<()>,
 }
 
 impl WebsocketService {
-    pub fn new() -> Self {
-        let ws = WebSocket::open("ws://127.0.0.1:8080").unwrap();
+    /// Opens a supervised connection. A `register` frame carrying `token` is
+    /// sent first on every (re)connect so the server knows who we are, and
+    /// the token is refreshed before that whenever it is about to expire or
+    /// the server turned it down.
+    /// `server` overrides the configured endpoint, see [`config::server_url`].
+    ///
+    /// Parsed frames and connection changes are published on the
+    /// [`EventBus`] under `Topic::Server` and `Topic::Connection`, refreshed
+    /// tokens and a session that cannot be refreshed under `Topic::Ui`.
+    pub fn new(username: String, server: Option<String>, token: Token) -> Self {
+        let (in_tx, in_rx) = futures::channel::mpsc::channel::<Outgoing>(1000);
+        let (alive_tx, alive_rx) = oneshot::channel();
+
+        spawn_local(supervise(username, server, token, in_rx, alive_tx));
 
-        let (mut write, mut read) = ws.split();
+        Self {
+            tx: in_tx,
+            _alive: alive_rx,
+        }
+    }
+}
 
-        let (in_tx, mut in_rx) = futures::channel::mpsc::channel::<String>(1000);
-        let mut event_bus = EventBus::dispatcher();
+impl PartialEq for WebsocketService {
+    /// Two handles are the same service when they feed the same connection.
+    fn eq(&self, other: &Self) -> bool {
+        self.tx.same_receiver(&other.tx)
+    }
+}
+
+/// A connection whose handshake the server has accepted, handed from the
+/// login screen to the chat so that it does not register a second time.
+#[derive(Debug, PartialEq)]
+pub struct Registered {
+    pub service: WebsocketService,
+    /// Users online when we were accepted, from the frame that confirmed it.
+    pub users: Vec<String>,
+}
 
-        spawn_local(async move {
-            while let Some(s) = in_rx.next().await {
-                log::debug!("got event from channel! {}", s);
-                write.send(Message::Text(s)).await.unwrap();
+/// Why a connection ended while the service was still wanted.
+enum Lost {
+    /// The socket failed or closed.
+    Error(String),
+    /// The server turned our token down, refresh it before reconnecting.
+    Unauthorized,
+}
+
+impl From<String> for Lost {
+    fn from(e: String) -> Self {
+        Lost::Error(e)
+    }
+}
+
+enum Io {
+    Incoming(Result<Message, WebSocketError>),
+    Outgoing(Outgoing),
+    Closed,
+    Shutdown,
+}
+
+async fn supervise(
+    username: String,
+    server: Option<String>,
+    mut token: Token,
+    mut in_rx: Receiver<Outgoing>,
+    mut alive: oneshot::Sender<()>,
+) {
+    let mut backoff = Backoff::default();
+    let mut bus = EventBus::dispatcher();
+    let mut refused = false;
+
+    set_state(&mut bus, ConnectionState::Connecting);
+    let url = config::server_url(server.as_deref()).await;
+    loop {
+        let attempt = match refresh_if_needed(server.as_deref(), &mut token, refused, &mut bus).await {
+            Ok(()) => {
+                refused = false;
+                let handshake = ClientFrame::register(username.clone(), Some(token.access.clone()));
+                run_connection(&url, &username, &handshake, &mut in_rx, &mut bus, &mut backoff).await
+            }
+            Err(AuthError::Rejected { reason, .. }) => {
+                log::warn!("session expired: {}", reason);
+                bus.send(Request::Publish(Event::Ui(UiEvent::SessionExpired)));
+                break;
             }
-        });
+            Err(e) => Err(Lost::Error(e.to_string())),
+        };
+        match attempt {
+            Ok(()) => break,
+            Err(Lost::Unauthorized) => {
+                log::info!("ws: the server refused our token, refreshing it");
+                refused = true;
+            }
+            Err(Lost::Error(e)) => log::warn!("ws: {}", e),
+        }
+        discard_stale(&mut in_rx);
+        if alive.is_canceled() {
+            break;
+        }
 
-        spawn_local(async move {
-            while let Some(msg) = read.next().await {
-                match msg {
-                    Ok(Message::Text(data)) => {
-                        log::debug!("from websocket: {}", data);
-                        event_bus.send(Request::EventBusMsg(data));
-                    }
-                    Ok(Message::Bytes(b)) => {
-                        let decoded = std::str::from_utf8(&b);
-                        if let Ok(val) = decoded {
-                            log::debug!("from websocket: {}", val);
-                            event_bus.send(Request::EventBusMsg(val.into()));
-                        }
-                    }
-                    Err(e) => {
-                        log::error!("ws: {:?}", e)
+        set_state(
+            &mut bus,
+            ConnectionState::Reconnecting {
+                attempt: backoff.attempt + 1,
+            },
+        );
+        let delay = backoff.next_delay();
+        log::debug!("reconnecting in {}ms", delay);
+        if let Either::Right(_) = future::select(TimeoutFuture::new(delay), alive.cancellation()).await {
+            break;
+        }
+        discard_stale(&mut in_rx);
+    }
+
+    set_state(&mut bus, ConnectionState::Disconnected);
+    log::debug!("WebSocket service stopped");
+}
+
+/// Drives a single socket until it closes or the server refuses our token.
+/// Returns `Ok` when the service itself was dropped and no reconnection
+/// should happen.
+async fn run_connection(
+    url: &str,
+    username: &str,
+    handshake: &ClientFrame,
+    in_rx: &mut Receiver<Outgoing>,
+    bus: &mut Dispatcher<EventBus>,
+    backoff: &mut Backoff,
+) -> Result<(), Lost> {
+    let ws = WebSocket::open(url).map_err(|e| e.to_string())?;
+    let (mut write, read) = ws.split();
+    let mut read = read.fuse();
+
+    // The sink only becomes ready once the socket is open, while a failed
+    // connection is reported on the read half, so race the two.
+    {
+        let send = write.send(Message::Text(handshake.to_json()));
+        futures::pin_mut!(send);
+        match future::select(send, read.next()).await {
+            Either::Left((Ok(()), _)) => {}
+            Either::Left((Err(e), _)) => return Err(Lost::Error(e.to_string())),
+            Either::Right((Some(Err(e)), _)) => return Err(Lost::Error(e.to_string())),
+            Either::Right(_) => return Err(Lost::Error("WebSocket Closed".into())),
+        }
+    }
+
+    log::debug!("WebSocket connected to {}", url);
+    set_state(bus, ConnectionState::Connected);
+    // Only a server that answers with something other than a refusal
+    // resets the backoff, so one refusing every token is not hammered.
+    let mut accepted = false;
+
+    let incoming = read.map(Io::Incoming).chain(stream::iter([Io::Closed]));
+    let outgoing = in_rx.map(Io::Outgoing).chain(stream::iter([Io::Shutdown]));
+    let mut events = stream::select(incoming, outgoing);
+
+    while let Some(event) = events.next().await {
+        match event {
+            Io::Outgoing(Outgoing::Frame(frame)) => {
+                log::debug!("got event from channel! {:?}", frame);
+                write.send(Message::Text(frame.to_json())).await.map_err(|e| e.to_string())?;
+            }
+            Io::Outgoing(Outgoing::Chunk(chunk)) => {
+                log::debug!("sending {} bytes of upload {}", chunk.data.len(), chunk.upload_id);
+                write.send(Message::Bytes(chunk.to_bytes())).await.map_err(|e| e.to_string())?;
+            }
+            Io::Incoming(Ok(Message::Text(data))) => {
+                log::debug!("from websocket: {}", data);
+                receive(bus, &data, username, backoff, &mut accepted)?;
+            }
+            Io::Incoming(Ok(Message::Bytes(b))) => {
+                // The server only sends JSON, binary frames are for uploads.
+                match std::str::from_utf8(&b) {
+                    Ok(val) => {
+                        log::debug!("from websocket: {}", val);
+                        receive(bus, val, username, backoff, &mut accepted)?;
                     }
+                    Err(_) => log::warn!("ignoring a binary frame of {} bytes", b.len()),
                 }
             }
-            log::debug!("WebSocket Closed");
-        });
+            Io::Incoming(Err(e)) => {
+                log::error!("ws: {:?}", e)
+            }
+            Io::Closed => return Err(Lost::Error("WebSocket Closed".into())),
+            Io::Shutdown => return Ok(()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Swaps `token` for a fresh one when it was `refused` or is about to
+/// expire, and tells the UI so it can be remembered.
+async fn refresh_if_needed(
+    server: Option<&str>,
+    token: &mut Token,
+    refused: bool,
+    bus: &mut Dispatcher<EventBus>,
+) -> Result<(), AuthError> {
+    if !refused && !token.expires_soon() {
+        return Ok(());
+    }
+    *token = auth::refresh(server, token).await?;
+    bus.send(Request::Publish(Event::Ui(UiEvent::TokenRefreshed(token.clone()))));
+    Ok(())
+}
+
+/// Drops what was queued for a connection that is gone. Typing, read and
+/// presence frames are stale by the next one, and the outbox and uploads
+/// send what matters again once they hear we are connected.
+fn discard_stale(in_rx: &mut Receiver<Outgoing>) {
+    let mut dropped = 0;
+    while let Ok(Some(_)) = in_rx.try_next() {
+        dropped += 1;
+    }
+    if dropped > 0 {
+        log::debug!("dropped {} frames queued while disconnected", dropped);
+    }
+}
+
+fn set_state(bus: &mut Dispatcher<EventBus>, state: ConnectionState) {
+    bus.send(Request::Publish(Event::Connection(state)));
+}
+
+/// Publishes a frame from the server. An `unauthorized` error is published
+/// too, for the login screen, but also ends the connection so the token
+/// gets refreshed.
+fn receive(
+    bus: &mut Dispatcher<EventBus>,
+    json: &str,
+    username: &str,
+    backoff: &mut Backoff,
+    accepted: &mut bool,
+) -> Result<(), Lost> {
+    let event = match ServerFrame::from_json(json) {
+        Ok(frame) => Event::Server(frame),
+        Err(e) => {
+            log::error!("Failed to parse websocket message: {:?}", e);
+            Event::Ui(UiEvent::Notice(format!("Failed to parse server message: {}", e)))
+        }
+    };
+    let refused = matches!(
+        event,
+        Event::Server(ServerFrame::Error {
+            code: Some(ErrorCode::Unauthorized),
+            ..
+        })
+    );
+    if let Event::Server(frame) = &event {
+        unfurl(frame, username);
+    }
+    bus.send(Request::Publish(event));
+
+    if refused {
+        return Err(Lost::Unauthorized);
+    }
+    if !*accepted {
+        backoff.reset();
+        *accepted = true;
+    }
+    Ok(())
+}
+
+/// Nothing to do, the server unfurls links itself.
+#[cfg(not(feature = "mock-server"))]
+fn unfurl(_frame: &ServerFrame, _username: &str) {}
+
+/// Makes up the link previews the server would send, see [`mock_server`].
+#[cfg(feature = "mock-server")]
+fn unfurl(frame: &ServerFrame, username: &str) {
+    mock_server::unfurl(frame, username);
+}
+
+/// Exponential backoff with jitter, so that a server restart does not get
+/// hit by every client at the same instant.
+#[derive(Default)]
+struct Backoff {
+    attempt: u32,
+}
+
+impl Backoff {
+    fn next_delay(&mut self) -> u32 {
+        let ceiling = BACKOFF_BASE_MS
+            .saturating_mul(1 << self.attempt.min(16))
+            .min(BACKOFF_MAX_MS);
+        self.attempt += 1;
+
+        // Keep at least half of the ceiling so retries never become a busy loop.
+        let jitter = js_sys::Math::random() * f64::from(ceiling / 2);
+        ceiling / 2 + jitter as u32
+    }
 
-        Self { tx: in_tx }
+    fn reset(&mut self) {
+        self.attempt = 0;
     }
 }
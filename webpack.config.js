@@ -3,6 +3,8 @@ const CopyWebpackPlugin = require('copy-webpack-plugin');
 const WasmPackPlugin = require('@wasm-tool/wasm-pack-plugin');
 
 const distPath = path.resolve(__dirname, 'dist');
+// `YEWCHAT_MOCK=1 npm start` answers auth requests in the browser.
+const features = ['wee_alloc'].concat(process.env.YEWCHAT_MOCK ? ['mock-server'] : []);
 
 module.exports = {
     mode: 'production',
@@ -21,7 +23,7 @@ module.exports = {
         }),
         new WasmPackPlugin({
             crateDirectory: '.',
-            extraArgs: '-- --features wee_alloc',
+            extraArgs: `-- --features ${features.join(',')}`,
             outName: 'yewchat',
         }),
     ],